@@ -42,5 +42,13 @@ mosquitto_sub $TLS_ARGS -h "$HOST" -p "$PORT" \
   -t "$TOPIC" -C 1 -W 10 -v \
   || { echo '[mqtt-test] FAIL (no message)'; exit 1; }
 
+# Optionally assert that mock-sink consumed the message (e.g. MOCK_SINK_URL=http://mock-sink:8081)
+if [ -n "${MOCK_SINK_URL:-}" ]; then
+  echo "[mqtt-test] Asserting mock-sink received the message..."
+  wget -qO- "${MOCK_SINK_URL}/received?topic=${TOPIC}&contains=ok&wait=30s" \
+    || { echo '[mqtt-test] FAIL (mock-sink did not receive message)'; exit 1; }
+  echo
+fi
+
 echo "[mqtt-test] OK"
 echo "[mqtt-test] Done"
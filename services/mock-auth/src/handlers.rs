@@ -1,8 +1,12 @@
+use crate::metrics;
 use crate::types::{
-    DeviceLoginReq, DeviceLoginResp, DeviceRegisterReq, DeviceRegisterResp, ServiceLoginReq,
-    ServiceLoginResp, TokenValidateReq, TokenValidateResp,
+    DeadLetter, DeviceLoginReq, DeviceLoginResp, DeviceRegisterReq, DeviceRegisterResp,
+    DeviceStatusResp, ServiceLoginReq, ServiceLoginResp, TokenValidateReq, TokenValidateResp,
+    WebhookCreateReq, WebhookSubscription,
 };
-use axum::http::HeaderMap;
+use crate::webhooks;
+use axum::extract::Path;
+use axum::http::{HeaderMap, header};
 use axum::{Json, http::StatusCode};
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
@@ -19,6 +23,38 @@ struct ServiceTokenInfo {
 static SERVICE_TOKENS: Lazy<RwLock<HashMap<String, ServiceTokenInfo>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
+#[derive(Clone)]
+struct DeviceTokenInfo {
+    device_id: String,
+    expires_at: OffsetDateTime,
+}
+
+static DEVICE_TOKENS: Lazy<RwLock<HashMap<String, DeviceTokenInfo>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Clone, Default)]
+struct DeviceRecord {
+    registered: bool,
+    approved: bool,
+    revoked: bool,
+    logged_in: bool,
+}
+
+/// With `MOCK_AUTH_REQUIRE_APPROVAL=true`, devices must be approved by an admin
+/// before they can log in or use their access tokens.
+fn approval_required() -> bool {
+    std::env::var("MOCK_AUTH_REQUIRE_APPROVAL").is_ok_and(|v| v == "true")
+}
+
+impl DeviceRecord {
+    fn is_allowed(&self) -> bool {
+        !self.revoked && (self.approved || !approval_required())
+    }
+}
+
+static DEVICES: Lazy<RwLock<HashMap<String, DeviceRecord>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
 fn cleanup_expired(tokens: &mut HashMap<String, ServiceTokenInfo>) {
     let now = OffsetDateTime::now_utc();
     tokens.retain(|_, info| info.expires_at > now);
@@ -27,6 +63,17 @@ fn cleanup_expired(tokens: &mut HashMap<String, ServiceTokenInfo>) {
 pub async fn register(
     headers: HeaderMap,
     Json(req): Json<DeviceRegisterReq>,
+) -> Result<Json<DeviceRegisterResp>, (StatusCode, String)> {
+    let result = register_device(headers, req).await;
+    metrics::REGISTRATIONS
+        .with_label_values(&[metrics::result_label(&result)])
+        .inc();
+    result
+}
+
+async fn register_device(
+    headers: HeaderMap,
+    req: DeviceRegisterReq,
 ) -> Result<Json<DeviceRegisterResp>, (StatusCode, String)> {
     let request_id = headers
         .get("x-request-id")
@@ -39,6 +86,15 @@ pub async fn register(
         tracing::warn!(%request_id, device_id = %req.device_id, "device register failed: invalid pre_shared_secret");
         return Err((StatusCode::UNAUTHORIZED, "invalid pre_shared_secret".into()));
     }
+    let first_registration = {
+        let mut devices = DEVICES.write().await;
+        let record = devices.entry(req.device_id.clone()).or_default();
+        if record.revoked {
+            tracing::warn!(%request_id, device_id = %req.device_id, "device register failed: device revoked");
+            return Err((StatusCode::FORBIDDEN, "device revoked".into()));
+        }
+        !std::mem::replace(&mut record.registered, true)
+    };
     let exp = OffsetDateTime::now_utc() + time::Duration::days(7);
     let expires_at = exp
         .format(&time::format_description::well_known::Rfc3339)
@@ -51,6 +107,9 @@ pub async fn register(
         expires_at: expires_at.clone(),
     };
     tracing::info!(%request_id, device_id = %resp.device_id, expires_at = %expires_at, "device registered successfully");
+    if first_registration {
+        webhooks::emit(webhooks::EVENT_DEVICE_REGISTERED, &resp.device_id).await;
+    }
     Ok(Json(resp))
 }
 
@@ -59,6 +118,17 @@ pub async fn register(
 pub async fn login(
     headers: HeaderMap,
     Json(req): Json<DeviceLoginReq>,
+) -> Result<Json<DeviceLoginResp>, (StatusCode, String)> {
+    let result = device_login(headers, req).await;
+    metrics::LOGINS
+        .with_label_values(&["device", metrics::result_label(&result)])
+        .inc();
+    result
+}
+
+async fn device_login(
+    headers: HeaderMap,
+    req: DeviceLoginReq,
 ) -> Result<Json<DeviceLoginResp>, (StatusCode, String)> {
     let request_id = headers
         .get("x-request-id")
@@ -70,6 +140,20 @@ pub async fn login(
         return Err((StatusCode::UNAUTHORIZED, "invalid token".into()));
     }
 
+    let first_login = {
+        let mut devices = DEVICES.write().await;
+        let record = devices.entry(req.device_id.clone()).or_default();
+        if record.revoked {
+            tracing::warn!(%request_id, device_id = %req.device_id, "device login failed: device revoked");
+            return Err((StatusCode::FORBIDDEN, "device revoked".into()));
+        }
+        if !record.is_allowed() {
+            tracing::warn!(%request_id, device_id = %req.device_id, "device login failed: device not approved");
+            return Err((StatusCode::FORBIDDEN, "device not approved".into()));
+        }
+        !std::mem::replace(&mut record.logged_in, true)
+    };
+
     let exp = OffsetDateTime::now_utc() + time::Duration::hours(1);
     let resp = DeviceLoginResp {
         access_token: Uuid::new_v4().to_string(),
@@ -77,7 +161,22 @@ pub async fn login(
             .format(&time::format_description::well_known::Rfc3339)
             .unwrap(),
     };
+    {
+        let mut tokens = DEVICE_TOKENS.write().await;
+        let now = OffsetDateTime::now_utc();
+        tokens.retain(|_, info| info.expires_at > now);
+        tokens.insert(
+            resp.access_token.clone(),
+            DeviceTokenInfo {
+                device_id: req.device_id.clone(),
+                expires_at: exp,
+            },
+        );
+    }
     tracing::info!(%request_id, device_id = %req.device_id, "device login success");
+    if first_login {
+        webhooks::emit(webhooks::EVENT_DEVICE_FIRST_LOGIN, &req.device_id).await;
+    }
     Ok(Json(resp))
 }
 
@@ -86,6 +185,17 @@ pub async fn login(
 pub async fn service_login(
     headers: HeaderMap,
     Json(req): Json<ServiceLoginReq>,
+) -> Result<Json<ServiceLoginResp>, (StatusCode, String)> {
+    let result = login_service(headers, req).await;
+    metrics::LOGINS
+        .with_label_values(&["service", metrics::result_label(&result)])
+        .inc();
+    result
+}
+
+async fn login_service(
+    headers: HeaderMap,
+    req: ServiceLoginReq,
 ) -> Result<Json<ServiceLoginResp>, (StatusCode, String)> {
     let request_id = headers
         .get("x-request-id")
@@ -151,10 +261,170 @@ pub async fn validate(
         }
     }
 
+    let mut device_id = None;
     if !valid {
-        valid = req.access_token.len() > 10;
+        let device_token = {
+            let mut tokens = DEVICE_TOKENS.write().await;
+            let now = OffsetDateTime::now_utc();
+            tokens.retain(|_, info| info.expires_at > now);
+            tokens
+                .get(req.access_token.as_str())
+                .map(|info| info.device_id.clone())
+        };
+        if let Some(id) = device_token {
+            valid = DEVICES
+                .read()
+                .await
+                .get(&id)
+                .is_some_and(DeviceRecord::is_allowed);
+            device_id = Some(id);
+        } else {
+            valid = req.access_token.len() > 10;
+        }
     }
 
-    tracing::info!(%request_id, %valid, service = ?service, "token validate");
-    Json(TokenValidateResp { valid, service })
+    tracing::info!(%request_id, %valid, service = ?service, device_id = ?device_id, "token validate");
+    Json(TokenValidateResp {
+        valid,
+        service,
+        device_id,
+    })
+}
+
+// --- Admin ---
+
+/// Admin routes stay closed until `MOCK_AUTH_ADMIN_TOKEN` is configured.
+fn ensure_admin(headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let expected = std::env::var("MOCK_AUTH_ADMIN_TOKEN").unwrap_or_default();
+    if expected.trim().is_empty() {
+        return Err((StatusCode::FORBIDDEN, "admin API disabled".into()));
+    }
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.strip_prefix("Bearer ")
+                .or_else(|| v.strip_prefix("bearer "))
+        })
+        .map(str::trim);
+    if provided != Some(expected.trim()) {
+        return Err((StatusCode::UNAUTHORIZED, "invalid admin token".into()));
+    }
+    Ok(())
+}
+
+pub async fn approve_device(
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+) -> Result<Json<DeviceStatusResp>, (StatusCode, String)> {
+    ensure_admin(&headers)?;
+    let (changed, resp) = {
+        let mut devices = DEVICES.write().await;
+        let record = devices
+            .get_mut(&device_id)
+            .ok_or((StatusCode::NOT_FOUND, "device not found".into()))?;
+        let changed = !std::mem::replace(&mut record.approved, true);
+        (changed, device_status(&device_id, record))
+    };
+    tracing::info!(device_id = %device_id, changed, "device approved");
+    if changed {
+        webhooks::emit(webhooks::EVENT_DEVICE_APPROVED, &device_id).await;
+    }
+    Ok(Json(resp))
+}
+
+pub async fn revoke_device(
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+) -> Result<Json<DeviceStatusResp>, (StatusCode, String)> {
+    ensure_admin(&headers)?;
+    let (changed, resp) = {
+        let mut devices = DEVICES.write().await;
+        let record = devices
+            .get_mut(&device_id)
+            .ok_or((StatusCode::NOT_FOUND, "device not found".into()))?;
+        let changed = !std::mem::replace(&mut record.revoked, true);
+        (changed, device_status(&device_id, record))
+    };
+    tracing::info!(device_id = %device_id, changed, "device revoked");
+    if changed {
+        webhooks::emit(webhooks::EVENT_DEVICE_REVOKED, &device_id).await;
+    }
+    Ok(Json(resp))
+}
+
+fn device_status(device_id: &str, record: &DeviceRecord) -> DeviceStatusResp {
+    DeviceStatusResp {
+        device_id: device_id.to_string(),
+        approved: record.approved,
+        revoked: record.revoked,
+    }
+}
+
+pub async fn create_webhook(
+    headers: HeaderMap,
+    Json(req): Json<WebhookCreateReq>,
+) -> Result<Json<WebhookSubscription>, (StatusCode, String)> {
+    ensure_admin(&headers)?;
+    if !(req.url.starts_with("http://") || req.url.starts_with("https://")) {
+        return Err((StatusCode::BAD_REQUEST, "url must be http(s)".into()));
+    }
+    if req.secret.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "secret is required".into()));
+    }
+    if let Some(unknown) = req
+        .events
+        .iter()
+        .find(|e| !webhooks::EVENT_TYPES.contains(&e.as_str()))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("unknown event '{unknown}'"),
+        ));
+    }
+
+    let sub = WebhookSubscription {
+        id: Uuid::new_v4().to_string(),
+        url: req.url,
+        secret: req.secret,
+        events: req.events,
+        created_at: OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .unwrap(),
+    };
+    webhooks::SUBSCRIPTIONS
+        .write()
+        .await
+        .insert(sub.id.clone(), sub.clone());
+    tracing::info!(subscription_id = %sub.id, url = %sub.url, "webhook subscription created");
+    Ok(Json(sub))
+}
+
+pub async fn list_webhooks(
+    headers: HeaderMap,
+) -> Result<Json<Vec<WebhookSubscription>>, (StatusCode, String)> {
+    ensure_admin(&headers)?;
+    let subs = webhooks::SUBSCRIPTIONS.read().await;
+    Ok(Json(subs.values().cloned().collect()))
+}
+
+pub async fn delete_webhook(
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    ensure_admin(&headers)?;
+    webhooks::SUBSCRIPTIONS
+        .write()
+        .await
+        .remove(&id)
+        .ok_or((StatusCode::NOT_FOUND, "webhook not found".into()))?;
+    tracing::info!(subscription_id = %id, "webhook subscription deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_dead_letters(
+    headers: HeaderMap,
+) -> Result<Json<Vec<DeadLetter>>, (StatusCode, String)> {
+    ensure_admin(&headers)?;
+    Ok(Json(webhooks::DEAD_LETTERS.read().await.clone()))
 }
@@ -1,13 +1,15 @@
 use axum::{
     Router,
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use serde_json::json;
 use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 
 pub mod handlers;
+pub mod metrics;
 pub mod types;
+pub mod webhooks;
 
 pub fn build_router() -> Router {
     Router::new()
@@ -15,10 +17,29 @@ pub fn build_router() -> Router {
         .route("/auth/device/login", post(handlers::login))
         .route("/auth/token/validate", post(handlers::validate))
         .route("/auth/service/login", post(handlers::service_login))
+        .route(
+            "/auth/admin/devices/:device_id/approve",
+            post(handlers::approve_device),
+        )
+        .route(
+            "/auth/admin/devices/:device_id/revoke",
+            post(handlers::revoke_device),
+        )
+        .route(
+            "/auth/admin/webhooks",
+            post(handlers::create_webhook).get(handlers::list_webhooks),
+        )
+        .route(
+            "/auth/admin/webhooks/dead-letters",
+            get(handlers::list_dead_letters),
+        )
+        .route("/auth/admin/webhooks/:id", delete(handlers::delete_webhook))
         .route(
             "/healthz",
             get(|| async { axum::Json(json!({"status": "ok"})) }),
         )
+        .route("/metrics", get(metrics::render))
+        .layer(axum::middleware::from_fn(metrics::track))
         .layer(
             TraceLayer::new_for_http().make_span_with(|req: &axum::http::Request<_>| {
                 let request_id = req
@@ -16,6 +16,7 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::EnvFilter::new(filter))
         .with(tracing_subscriber::fmt::layer())
         .init();
+    mock_auth::metrics::init();
     let app: Router = build_router();
 
     // Bind host/port from env with sensible defaults. Prefer service-specific vars.
@@ -0,0 +1,34 @@
+use prometheus::{IntCounterVec, register_int_counter_vec};
+use service_metrics::RESULTS;
+use std::sync::LazyLock;
+
+pub use service_metrics::{render, result_label, track};
+
+pub static REGISTRATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mock_auth_registrations_total",
+        "Device registrations by result (ok/error)",
+        &["result"]
+    )
+    .expect("register mock_auth_registrations_total")
+});
+
+pub static LOGINS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mock_auth_logins_total",
+        "Logins by kind (device/service) and result (ok/error)",
+        &["kind", "result"]
+    )
+    .expect("register mock_auth_logins_total")
+});
+
+/// Register every collector up front so dashboards see zeroes, not gaps.
+pub fn init() {
+    service_metrics::init();
+    for result in RESULTS {
+        REGISTRATIONS.with_label_values(&[result]);
+        for kind in ["device", "service"] {
+            LOGINS.with_label_values(&[kind, result]);
+        }
+    }
+}
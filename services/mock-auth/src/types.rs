@@ -49,4 +49,50 @@ pub struct TokenValidateResp {
     pub valid: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookCreateReq {
+    pub url: String,
+    pub secret: String,
+    /// Event types to deliver; empty means every lifecycle event.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct WebhookSubscription {
+    pub id: String,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created_at: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct WebhookEvent {
+    pub id: String,
+    pub event: String,
+    pub device_id: String,
+    pub occurred_at: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DeadLetter {
+    pub subscription_id: String,
+    pub url: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub failed_at: String,
+    pub event: WebhookEvent,
+}
+
+#[derive(Serialize)]
+pub struct DeviceStatusResp {
+    pub device_id: String,
+    pub approved: bool,
+    pub revoked: bool,
 }
@@ -0,0 +1,158 @@
+use crate::types::{DeadLetter, WebhookEvent, WebhookSubscription};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+pub const EVENT_DEVICE_REGISTERED: &str = "device.registered";
+pub const EVENT_DEVICE_FIRST_LOGIN: &str = "device.first_login";
+pub const EVENT_DEVICE_REVOKED: &str = "device.revoked";
+pub const EVENT_DEVICE_APPROVED: &str = "device.approved";
+
+pub const EVENT_TYPES: [&str; 4] = [
+    EVENT_DEVICE_REGISTERED,
+    EVENT_DEVICE_FIRST_LOGIN,
+    EVENT_DEVICE_REVOKED,
+    EVENT_DEVICE_APPROVED,
+];
+
+pub const SIGNATURE_HEADER: &str = "x-argus-signature";
+pub const TIMESTAMP_HEADER: &str = "x-argus-timestamp";
+pub const EVENT_HEADER: &str = "x-argus-event";
+pub const DELIVERY_HEADER: &str = "x-argus-delivery";
+
+// Oldest dead letters are dropped once the list grows past this size.
+const DEAD_LETTER_LIMIT: usize = 1000;
+
+pub static SUBSCRIPTIONS: Lazy<RwLock<HashMap<String, WebhookSubscription>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub static DEAD_LETTERS: Lazy<RwLock<Vec<DeadLetter>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+static HTTP: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
+
+struct RetryPolicy {
+    max_attempts: u32,
+    base_backoff: Duration,
+    max_backoff: Duration,
+    timeout: Duration,
+}
+
+impl RetryPolicy {
+    fn from_env() -> Self {
+        Self {
+            max_attempts: env_u64("MOCK_AUTH_WEBHOOK_MAX_ATTEMPTS", 5).max(1) as u32,
+            base_backoff: Duration::from_millis(env_u64("MOCK_AUTH_WEBHOOK_BACKOFF_MS", 500)),
+            max_backoff: Duration::from_millis(env_u64("MOCK_AUTH_WEBHOOK_BACKOFF_MAX_MS", 30_000)),
+            timeout: Duration::from_millis(env_u64("MOCK_AUTH_WEBHOOK_TIMEOUT_MS", 5_000)),
+        }
+    }
+
+    /// Delay before retry number `attempt` (1-based): base * 2^(attempt-1), capped.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+        self.base_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+fn env_u64(key: &str, default: u64) -> u64 {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn now_rfc3339() -> String {
+    OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap()
+}
+
+/// Hex-encoded HMAC-SHA256 over `"{timestamp}.{body}"`.
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Queue delivery of a lifecycle event to every matching subscription.
+pub async fn emit(event: &str, device_id: &str) {
+    let payload = WebhookEvent {
+        id: Uuid::new_v4().to_string(),
+        event: event.to_string(),
+        device_id: device_id.to_string(),
+        occurred_at: now_rfc3339(),
+    };
+
+    let targets: Vec<WebhookSubscription> = {
+        let subs = SUBSCRIPTIONS.read().await;
+        subs.values()
+            .filter(|s| s.events.is_empty() || s.events.iter().any(|e| e == event))
+            .cloned()
+            .collect()
+    };
+
+    for sub in targets {
+        let payload = payload.clone();
+        tokio::spawn(async move { deliver(sub, payload).await });
+    }
+}
+
+async fn deliver(sub: WebhookSubscription, event: WebhookEvent) {
+    let policy = RetryPolicy::from_env();
+    let body = serde_json::to_vec(&event).expect("webhook event serializes");
+    let mut last_error = String::new();
+
+    for attempt in 1..=policy.max_attempts {
+        let timestamp = OffsetDateTime::now_utc().unix_timestamp().to_string();
+        let signature = sign(&sub.secret, &timestamp, &body);
+        let result = HTTP
+            .post(&sub.url)
+            .timeout(policy.timeout)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={signature}"))
+            .header(TIMESTAMP_HEADER, &timestamp)
+            .header(EVENT_HEADER, &event.event)
+            .header(DELIVERY_HEADER, &event.id)
+            .body(body.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(resp) if resp.status().is_success() => {
+                tracing::info!(subscription_id = %sub.id, event = %event.event, delivery = %event.id, attempt, "webhook delivered");
+                return;
+            }
+            Ok(resp) => last_error = format!("receiver returned {}", resp.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+
+        tracing::warn!(subscription_id = %sub.id, event = %event.event, delivery = %event.id, attempt, error = %last_error, "webhook delivery failed");
+        if attempt < policy.max_attempts {
+            tokio::time::sleep(policy.backoff(attempt)).await;
+        }
+    }
+
+    tracing::error!(subscription_id = %sub.id, event = %event.event, delivery = %event.id, "webhook moved to dead-letter list");
+    let mut dead = DEAD_LETTERS.write().await;
+    if dead.len() >= DEAD_LETTER_LIMIT {
+        dead.remove(0);
+    }
+    dead.push(DeadLetter {
+        subscription_id: sub.id,
+        url: sub.url,
+        attempts: policy.max_attempts,
+        last_error,
+        failed_at: now_rfc3339(),
+        event,
+    });
+}
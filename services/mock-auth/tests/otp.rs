@@ -1,2 +1,4 @@
 #[path = "otp/cases.rs"]
 mod cases;
+#[path = "otp/webhooks.rs"]
+mod webhooks;
@@ -1,9 +1,9 @@
 use axum::{
-    body::{to_bytes, Body},
+    body::{Body, to_bytes},
     http::{Request, StatusCode},
 };
 use mock_auth::build_router;
-use serde_json::{json, Value};
+use serde_json::{Value, json};
 use tower::util::ServiceExt; // for `oneshot`
 
 #[tokio::test]
@@ -51,7 +51,7 @@ async fn register_ok() {
     let bytes = to_bytes(resp.into_body(), 64 * 1024).await.unwrap();
     let v: Value = serde_json::from_slice(&bytes).unwrap();
     assert_eq!(v["device_id"], "test-device");
-    assert!(v["token"].as_str().unwrap().len() > 0);
+    assert!(!v["token"].as_str().unwrap().is_empty());
 }
 
 #[tokio::test]
@@ -157,6 +157,7 @@ async fn login_then_validate() {
         serde_json::from_slice(&to_bytes(val_resp.into_body(), 64 * 1024).await.unwrap()).unwrap();
     assert_eq!(val_json["valid"], true);
     assert!(val_json["service"].is_null());
+    assert_eq!(val_json["device_id"], "test-device");
 }
 
 #[tokio::test]
@@ -216,3 +217,49 @@ async fn service_login_and_validate() {
     assert_eq!(resp_json["valid"], true);
     assert_eq!(resp_json["service"], Value::String("mock-ota".into()));
 }
+
+#[tokio::test]
+#[serial_test::serial]
+async fn metrics_count_service_logins() {
+    let app = build_router();
+    let before = mock_auth::metrics::LOGINS
+        .with_label_values(&["service", "error"])
+        .get();
+    let body = json!({"service": "mock-ota", "secret": "wrong"}).to_string();
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/auth/service/login")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        mock_auth::metrics::LOGINS
+            .with_label_values(&["service", "error"])
+            .get(),
+        before + 1
+    );
+
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = to_bytes(resp.into_body(), 1024 * 1024).await.unwrap();
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(text.contains(
+        r#"http_requests_total{method="POST",route="/auth/service/login",status="401"}"#
+    ));
+    assert!(text.contains("mock_auth_logins_total"));
+}
@@ -0,0 +1,332 @@
+use axum::{
+    Router,
+    body::{Body, Bytes, to_bytes},
+    http::{HeaderMap, Request, StatusCode},
+    routing::post,
+};
+use mock_auth::{build_router, webhooks};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, sleep};
+use tower::util::ServiceExt; // for `oneshot`
+
+type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+const ADMIN_TOKEN: &str = "admin-secret";
+
+async fn spawn_receiver(status: StatusCode) -> (String, Received) {
+    let received: Received = Arc::default();
+    let sink = Arc::clone(&received);
+    let router = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap, body: Bytes| {
+            let sink = Arc::clone(&sink);
+            async move {
+                sink.lock().await.push((headers, body));
+                status
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    (format!("http://{addr}/hook"), received)
+}
+
+async fn post_json(app: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {ADMIN_TOKEN}"))
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = resp.status();
+    let bytes = to_bytes(resp.into_body(), 64 * 1024).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+async fn get_json(app: &Router, uri: &str) -> Value {
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(uri)
+                .header("authorization", format!("Bearer {ADMIN_TOKEN}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    serde_json::from_slice(&to_bytes(resp.into_body(), 64 * 1024).await.unwrap()).unwrap()
+}
+
+async fn delete_webhook(app: &Router, id: &str) {
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/auth/admin/webhooks/{id}"))
+                .header("authorization", format!("Bearer {ADMIN_TOKEN}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn lifecycle_events_are_signed_and_delivered() {
+    unsafe {
+        std::env::set_var("MOCK_AUTH_ADMIN_TOKEN", ADMIN_TOKEN);
+        std::env::set_var("MOCK_AUTH_ACCEPT_ANY_SECRET", "true");
+    }
+    let app = build_router();
+    let (url, received) = spawn_receiver(StatusCode::OK).await;
+
+    let (status, sub) = post_json(
+        &app,
+        "/auth/admin/webhooks",
+        json!({"url": url, "secret": "hook-secret"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(sub.get("secret").is_none());
+    let sub_id = sub["id"].as_str().unwrap().to_string();
+
+    let device_id = "webhook-device";
+    let mut token = String::new();
+    // re-registering must not announce the device again
+    for _ in 0..2 {
+        let (status, reg) = post_json(
+            &app,
+            "/auth/device/register",
+            json!({"device_id": device_id, "pre_shared_secret": "secret123"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        token = reg["token"].as_str().unwrap().to_string();
+    }
+    for _ in 0..2 {
+        let (status, _) = post_json(
+            &app,
+            "/auth/device/login",
+            json!({"device_id": device_id, "token": token}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, _) = post_json(
+        &app,
+        &format!("/auth/admin/devices/{device_id}/approve"),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = post_json(
+        &app,
+        &format!("/auth/admin/devices/{device_id}/revoke"),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    for _ in 0..50 {
+        if received.lock().await.len() >= 4 {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    delete_webhook(&app, &sub_id).await;
+
+    let received = received.lock().await;
+    let mut events: Vec<String> = Vec::new();
+    for (headers, body) in received.iter() {
+        let timestamp = headers[webhooks::TIMESTAMP_HEADER].to_str().unwrap();
+        let expected = format!("sha256={}", webhooks::sign("hook-secret", timestamp, body));
+        assert_eq!(
+            headers[webhooks::SIGNATURE_HEADER].to_str().unwrap(),
+            expected
+        );
+        let payload: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["device_id"], device_id);
+        events.push(payload["event"].as_str().unwrap().to_string());
+    }
+    events.sort();
+    assert_eq!(
+        events,
+        vec![
+            "device.approved",
+            "device.first_login",
+            "device.registered",
+            "device.revoked"
+        ]
+    );
+
+    // revoked devices can no longer log in
+    let (status, _) = post_json(
+        &app,
+        "/auth/device/login",
+        json!({"device_id": device_id, "token": token}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn failed_deliveries_land_in_dead_letters() {
+    unsafe {
+        std::env::set_var("MOCK_AUTH_ADMIN_TOKEN", ADMIN_TOKEN);
+        std::env::set_var("MOCK_AUTH_ACCEPT_ANY_SECRET", "true");
+        std::env::set_var("MOCK_AUTH_WEBHOOK_MAX_ATTEMPTS", "3");
+        std::env::set_var("MOCK_AUTH_WEBHOOK_BACKOFF_MS", "5");
+    }
+    let app = build_router();
+    let (url, received) = spawn_receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+
+    let (status, sub) = post_json(
+        &app,
+        "/auth/admin/webhooks",
+        json!({"url": url, "secret": "hook-secret", "events": ["device.registered"]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let sub_id = sub["id"].as_str().unwrap().to_string();
+
+    let (status, _) = post_json(
+        &app,
+        "/auth/device/register",
+        json!({"device_id": "dead-letter-device", "pre_shared_secret": "secret123"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut dead = Value::Null;
+    for _ in 0..100 {
+        dead = get_json(&app, "/auth/admin/webhooks/dead-letters").await;
+        if dead
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|d| d["subscription_id"] == sub_id.as_str())
+        {
+            break;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    delete_webhook(&app, &sub_id).await;
+    unsafe {
+        std::env::remove_var("MOCK_AUTH_WEBHOOK_MAX_ATTEMPTS");
+        std::env::remove_var("MOCK_AUTH_WEBHOOK_BACKOFF_MS");
+    }
+
+    let entry = dead
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| d["subscription_id"] == sub_id.as_str())
+        .expect("dead letter recorded");
+    assert_eq!(entry["attempts"], 3);
+    assert_eq!(entry["event"]["event"], "device.registered");
+    assert_eq!(received.lock().await.len(), 3);
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn admin_routes_require_the_configured_token() {
+    unsafe {
+        std::env::remove_var("MOCK_AUTH_ADMIN_TOKEN");
+    }
+    let app = build_router();
+    let unauthenticated = || {
+        Request::builder()
+            .uri("/auth/admin/webhooks")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let resp = app.clone().oneshot(unauthenticated()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    unsafe {
+        std::env::set_var("MOCK_AUTH_ADMIN_TOKEN", ADMIN_TOKEN);
+    }
+    let resp = app.clone().oneshot(unauthenticated()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri("/auth/admin/webhooks")
+                .header("authorization", format!("Bearer {ADMIN_TOKEN}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    unsafe {
+        std::env::remove_var("MOCK_AUTH_ADMIN_TOKEN");
+    }
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn unapproved_devices_cannot_log_in_when_approval_is_required() {
+    unsafe {
+        std::env::set_var("MOCK_AUTH_ADMIN_TOKEN", ADMIN_TOKEN);
+        std::env::set_var("MOCK_AUTH_ACCEPT_ANY_SECRET", "true");
+        std::env::set_var("MOCK_AUTH_REQUIRE_APPROVAL", "true");
+    }
+    let app = build_router();
+    let device_id = "approval-device";
+    let (status, reg) = post_json(
+        &app,
+        "/auth/device/register",
+        json!({"device_id": device_id, "pre_shared_secret": "secret123"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let login = json!({"device_id": device_id, "token": reg["token"]});
+
+    let (status, _) = post_json(&app, "/auth/device/login", login.clone()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = post_json(
+        &app,
+        &format!("/auth/admin/devices/{device_id}/approve"),
+        json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, session) = post_json(&app, "/auth/device/login", login).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, validated) = post_json(
+        &app,
+        "/auth/token/validate",
+        json!({"access_token": session["access_token"]}),
+    )
+    .await;
+    unsafe {
+        std::env::remove_var("MOCK_AUTH_REQUIRE_APPROVAL");
+        std::env::remove_var("MOCK_AUTH_ADMIN_TOKEN");
+    }
+    assert_eq!(validated["valid"], true);
+    assert_eq!(validated["device_id"], device_id);
+}
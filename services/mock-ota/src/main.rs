@@ -1,3 +1,5 @@
+mod metrics;
+
 use std::{
     collections::HashMap,
     net::SocketAddr,
@@ -16,15 +18,18 @@ use axum::{
     routing::{get, post},
 };
 use chrono::{DateTime, Utc};
+use mqtt_client::{
+    Backoff, Client as MqttClient, ConnectionState, Endpoint, Event, Options, Properties, Protocol,
+    Scheme,
+};
 use reqwest::Client;
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS, TlsConfiguration, Transport};
 use serde::{Deserialize, Serialize};
 use tokio::{fs, sync::RwLock};
 use tokio_util::io::ReaderStream;
+use topic_layout::TopicLayout;
 use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use url::Url;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -38,6 +43,24 @@ enum JobStatus {
 }
 
 impl JobStatus {
+    const ALL: [Self; 5] = [
+        Self::Created,
+        Self::Dispatched,
+        Self::InProgress,
+        Self::Completed,
+        Self::Failed,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Created => "created",
+            JobStatus::Dispatched => "dispatched",
+            JobStatus::InProgress => "in_progress",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+        }
+    }
+
     fn is_terminal(&self) -> bool {
         matches!(self, JobStatus::Completed | JobStatus::Failed)
     }
@@ -72,7 +95,8 @@ struct OtaCommand {
 
 #[derive(Debug, Deserialize)]
 struct DeviceStatusPayload {
-    job_id: Uuid,
+    /// Falls back to the MQTT v5 correlation data.
+    job_id: Option<Uuid>,
     status: String,
     message: Option<String>,
 }
@@ -81,8 +105,12 @@ struct AppState {
     jobs: RwLock<HashMap<Uuid, OtaJob>>,
     artifact_dir: PathBuf,
     public_base: String,
-    topic_prefix: String,
-    mqtt: AsyncClient,
+    topics: TopicLayout,
+    mqtt: MqttClient,
+    /// MQTT v5 message expiry for OTA commands, in seconds.
+    command_expiry: Option<u32>,
+    /// Broker connection as seen by the MQTT event loop.
+    connection: ConnectionState,
     auth: AuthContext,
 }
 
@@ -94,10 +122,6 @@ struct AuthContext {
 }
 
 impl AppState {
-    fn ota_command_topic(&self, device_id: &str) -> String {
-        format!("{}{}{}", self.topic_prefix, device_id, "/ota")
-    }
-
     fn artifact_url(&self, artifact: &str) -> String {
         format!(
             "{}/ota/artifacts/{}",
@@ -306,13 +330,23 @@ async fn dispatch_job(
     };
     let payload = serde_json::to_vec(&command)
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    let topic = state.ota_command_topic(&device_id);
+    let topic = state
+        .topics
+        .ota_command_topic(&device_id)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    // MQTT v5 devices can report status via the response topic and
+    // correlation data
+    let properties = Properties {
+        content_type: Some("application/json".into()),
+        response_topic: state.topics.ota_status_topic(&device_id).ok(),
+        correlation_data: Some(id.to_string().into_bytes()),
+        user_properties: Vec::new(),
+        message_expiry: state.command_expiry,
+    };
 
-    state
-        .mqtt
-        .publish(topic.clone(), QoS::AtLeastOnce, false, payload)
-        .await
-        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("mqtt publish failed: {e}")))?;
+    let result = state.mqtt.publish(topic.clone(), payload, properties).await;
+    metrics::record_publish(&result);
+    result.map_err(|e| (StatusCode::BAD_GATEWAY, format!("mqtt publish failed: {e}")))?;
 
     {
         let mut jobs = state.jobs.write().await;
@@ -341,10 +375,10 @@ async fn list_artifacts(
     let mut files = Vec::new();
     while let Some(entry) = entries.next_entry().await.map_err(internal_error)? {
         let file_type = entry.file_type().await.map_err(internal_error)?;
-        if file_type.is_file() {
-            if let Some(name) = entry.file_name().to_str() {
-                files.push(name.to_string());
-            }
+        if file_type.is_file()
+            && let Some(name) = entry.file_name().to_str()
+        {
+            files.push(name.to_string());
         }
     }
     files.sort();
@@ -375,32 +409,42 @@ async fn get_artifact(
     Ok(response)
 }
 
-async fn healthz() -> Json<serde_json::Value> {
-    Json(serde_json::json!({"status": "ok"}))
+async fn healthz(State(state): State<SharedState>) -> Json<serde_json::Value> {
+    let mqtt = state.connection.status();
+    Json(serde_json::json!({
+        "status": "ok",
+        "mqtt": {
+            "connected": mqtt.connected,
+            "connected_since": mqtt.connected_since.map(DateTime::<Utc>::from),
+            "failures": mqtt.failures,
+            "last_error": mqtt.last_error,
+        },
+    }))
+}
+
+async fn metrics_endpoint(State(state): State<SharedState>) -> Response {
+    metrics::set_jobs(state.jobs.read().await.values());
+    metrics::render().await
 }
 
 fn internal_error<E: std::fmt::Display>(err: E) -> (StatusCode, String) {
     (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
 }
 
-fn parse_status_topic<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
-    let rest = topic.strip_prefix(prefix)?;
-    let mut parts = rest.split('/');
-    let device = parts.next()?;
-    if device.is_empty() {
-        return None;
-    }
-    let seg1 = parts.next()?;
-    let seg2 = parts.next()?;
-    if seg1 == "ota" && seg2 == "status" && parts.next().is_none() {
-        Some(device)
-    } else {
-        None
-    }
+/// Job a status report is about: its `job_id`, else the correlation data.
+fn status_job_id(status: &DeviceStatusPayload, correlation_data: Option<&[u8]>) -> Option<Uuid> {
+    status
+        .job_id
+        .or_else(|| std::str::from_utf8(correlation_data?).ok()?.parse().ok())
 }
 
-async fn handle_status_message(state: &AppState, topic: &str, payload: &[u8]) {
-    let Some(device_id) = parse_status_topic(&state.topic_prefix, topic) else {
+async fn handle_status_message(
+    state: &AppState,
+    topic: &str,
+    payload: &[u8],
+    correlation_data: Option<&[u8]>,
+) {
+    let Some(device_id) = state.topics.parse_ota_status(topic) else {
         return;
     };
 
@@ -412,13 +456,17 @@ async fn handle_status_message(state: &AppState, topic: &str, payload: &[u8]) {
         );
         return;
     };
+    let Some(job_id) = status_job_id(&status_payload, correlation_data) else {
+        tracing::warn!(target = "mock-ota", topic, "OTA status has no job id");
+        return;
+    };
 
     let mut jobs = state.jobs.write().await;
-    if let Some(job) = jobs.get_mut(&status_payload.job_id) {
+    if let Some(job) = jobs.get_mut(&job_id) {
         let new_status = match status_payload.status.as_str() {
-            s if matches!(s, "in_progress" | "downloading" | "installing") => JobStatus::InProgress,
-            s if matches!(s, "completed" | "success" | "ok") => JobStatus::Completed,
-            s if matches!(s, "failed" | "error") => JobStatus::Failed,
+            "in_progress" | "downloading" | "installing" => JobStatus::InProgress,
+            "completed" | "success" | "ok" => JobStatus::Completed,
+            "failed" | "error" => JobStatus::Failed,
             _ => JobStatus::InProgress,
         };
         if job.dispatched_at.is_none() {
@@ -438,19 +486,21 @@ async fn handle_status_message(state: &AppState, topic: &str, payload: &[u8]) {
             "updated OTA job status"
         );
     } else {
-        tracing::info!(job_id = %status_payload.job_id, device_id, "received status for unknown job");
+        tracing::info!(%job_id, device_id, "received status for unknown job");
     }
 }
 
 fn build_router(state: SharedState) -> Router {
     Router::new()
         .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics_endpoint))
         .route("/ota/jobs", post(create_job).get(list_jobs))
         .route("/ota/jobs/:id", get(get_job))
         .route("/ota/jobs/:id/dispatch", post(dispatch_job))
         .route("/ota/artifacts", get(list_artifacts))
         .route("/ota/artifacts/:name", get(get_artifact))
         .with_state(state)
+        .layer(axum::middleware::from_fn(metrics::track))
         .layer(
             TraceLayer::new_for_http().make_span_with(|req: &axum::http::Request<_>| {
                 let request_id = req
@@ -474,11 +524,12 @@ async fn main() -> anyhow::Result<()> {
         .with(tracing_subscriber::EnvFilter::new(filter))
         .with(tracing_subscriber::fmt::layer())
         .init();
+    metrics::init();
 
     let host = read_env("MOCK_OTA_HOST", "0.0.0.0");
     let port: u16 = read_env("MOCK_OTA_PORT", "8090").parse().unwrap_or(8090);
     let public_base = read_env("MOCK_OTA_PUBLIC_BASE", "http://mock-ota:8090");
-    let topic_prefix = ensure_trailing_slash(read_env("MQTT_TOPIC_PREFIX", "argus/devices/"));
+    let topics = TopicLayout::from_env().context("invalid MQTT topic layout")?;
     let artifact_dir = PathBuf::from(read_env("MOCK_OTA_ARTIFACT_DIR", "/artifacts"));
     let validate_url = read_env(
         "MOCK_AUTH_VALIDATE_URL",
@@ -488,81 +539,60 @@ async fn main() -> anyhow::Result<()> {
 
     let mqtt_username = read_env("MQTT_USERNAME", "devuser");
     let mqtt_password = read_env("MQTT_PASSWORD", "devpass");
-    let mqtt_default_host = read_env("MQTT_HOST", "mqtt");
-    let mqtt_default_port: u16 = read_env("MQTT_PORT", "8883").parse().unwrap_or(8883);
-
-    let (mqtt_host, mqtt_port) = match read_env_optional("MQTT_URL") {
-        Some(url) if !url.is_empty() => match Url::parse(&url) {
-            Ok(parsed) => {
-                let host = parsed.host_str().unwrap_or(&mqtt_default_host).to_string();
-                let port = parsed.port().unwrap_or(mqtt_default_port);
-                (host, port)
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "MQTT_URL parse error: {e}; falling back to {}:{}",
-                    mqtt_default_host,
-                    mqtt_default_port
-                );
-                (mqtt_default_host, mqtt_default_port)
-            }
-        },
-        _ => (mqtt_default_host, mqtt_default_port),
-    };
-
-    tracing::info!("mqtt -> {mqtt_host}:{mqtt_port} as mock-ota");
-
-    let mut opts = MqttOptions::new("mock-ota", mqtt_host, mqtt_port);
-    opts.set_keep_alive(Duration::from_secs(30));
-    opts.set_credentials(mqtt_username, mqtt_password);
-
-    let ca_path = read_env("MQTT_CA_PATH", "/certs/ca.crt");
-    let ca_bytes = fs::read(&ca_path)
-        .await
-        .with_context(|| format!("failed to read MQTT_CA_PATH at {ca_path}"))?;
-    let client_auth = match (
-        read_env_optional("MQTT_CERT_PATH"),
-        read_env_optional("MQTT_KEY_PATH"),
-    ) {
-        (Some(cert), Some(key)) => {
-            let cert_bytes = fs::read(&cert)
-                .await
-                .with_context(|| format!("failed to read MQTT_CERT_PATH at {cert}"))?;
-            let key_bytes = fs::read(&key)
-                .await
-                .with_context(|| format!("failed to read MQTT_KEY_PATH at {key}"))?;
-            Some((cert_bytes, key_bytes))
-        }
-        (None, None) => None,
-        _ => {
-            tracing::warn!(
-                "MQTT client certificate/key not fully specified; proceeding without client auth"
-            );
-            None
+    let default_endpoint = Endpoint::new(
+        Scheme::Tls,
+        &read_env("MQTT_HOST", "mqtt"),
+        read_env("MQTT_PORT", "8883").parse().unwrap_or(8883),
+    );
+    let endpoint = match read_env_optional("MQTT_URL").map(|url| Endpoint::parse(&url)) {
+        Some(Ok(endpoint)) => endpoint,
+        Some(Err(e)) => {
+            tracing::warn!("MQTT_URL parse error: {e}; falling back to {default_endpoint}");
+            default_endpoint
         }
+        None => default_endpoint,
     };
+    if endpoint.is_plain_on_tls_port() {
+        tracing::warn!(
+            "MQTT_URL {endpoint} uses plain TCP on the TLS port; use mqtts:// for a TLS listener"
+        );
+    }
 
-    let transport = Transport::tls_with_config(TlsConfiguration::Simple {
-        ca: ca_bytes,
-        alpn: None,
-        client_auth,
-    });
-    opts.set_transport(transport);
-
-    let (client, mut eventloop) = AsyncClient::new(opts, 32);
+    let protocol = Protocol::parse(&read_env("MQTT_PROTOCOL", "3.1.1"))
+        .context("MQTT_PROTOCOL must be 3.1.1 or 5")?;
+    tracing::info!("mqtt -> {endpoint} as mock-ota (MQTT {protocol})");
+
+    // Certificates are only needed for mqtts:// and wss:// brokers.
+    let tls = endpoint.tls_from_env()?;
+    let (client, mut eventloop) = MqttClient::new(
+        protocol,
+        Options {
+            client_id: "mock-ota".into(),
+            endpoint,
+            tls,
+            credentials: Some((mqtt_username, mqtt_password)),
+            keep_alive: Duration::from_secs(30),
+        },
+        32,
+    );
     let http_client = Client::builder().build()?;
 
-    client
-        .subscribe(topic_prefix.clone() + "+/ota/status", QoS::AtLeastOnce)
-        .await
-        .context("subscribe to OTA status topic")?;
+    // Subscribed on every ConnAck, since a clean-session broker forgets it
+    let status_filter = topics.ota_status().filter();
+    let reconnect_max = read_env("MQTT_RECONNECT_MAX_SECS", "30");
+    let command_expiry = read_env("MOCK_OTA_COMMAND_EXPIRY_SECS", "0")
+        .parse::<u32>()
+        .ok()
+        .filter(|secs| *secs > 0);
 
     let state = Arc::new(AppState {
         jobs: RwLock::new(HashMap::new()),
         artifact_dir,
         public_base,
-        topic_prefix,
+        topics,
         mqtt: client.clone(),
+        command_expiry,
+        connection: ConnectionState::default(),
         auth: AuthContext {
             client: http_client,
             validate_url,
@@ -572,21 +602,44 @@ async fn main() -> anyhow::Result<()> {
 
     let mqtt_state = Arc::clone(&state);
     tokio::spawn(async move {
+        let mut backoff = Backoff::from_max_secs(&reconnect_max);
         loop {
             match eventloop.poll().await {
-                Ok(Event::Incoming(Incoming::Publish(publish))) => {
-                    let topic = publish.topic.clone();
-                    handle_status_message(&mqtt_state, &topic, &publish.payload).await;
+                Ok(Event::ConnAck { session_present }) => {
+                    tracing::info!("mqtt connected (session present: {session_present})");
+                    mqtt_state.connection.on_connack();
+                    backoff.reset();
+                    // Subscribing waits on the request channel this loop drains
+                    let client = mqtt_state.mqtt.clone();
+                    let filter = status_filter.clone();
+                    tokio::spawn(async move {
+                        match client.subscribe(&filter).await {
+                            Ok(_) => tracing::info!("subscribed: {filter}"),
+                            Err(e) => tracing::error!("subscribe error for '{filter}': {e}"),
+                        }
+                    });
+                }
+                Ok(Event::Publish(publish)) => {
+                    handle_status_message(
+                        &mqtt_state,
+                        &publish.topic,
+                        &publish.payload,
+                        publish.properties.correlation_data.as_deref(),
+                    )
+                    .await;
                 }
-                Ok(Event::Incoming(other)) => {
-                    tracing::trace!("mqtt incoming: {other:?}");
+                Ok(Event::SubAck { pkid, refused }) if !refused.is_empty() => {
+                    tracing::error!("mqtt subscribe pkid={pkid} refused: {refused:?}");
                 }
-                Ok(Event::Outgoing(out)) => {
-                    tracing::trace!("mqtt outgoing: {out:?}");
+                Ok(other) => {
+                    tracing::trace!("mqtt event: {other:?}");
                 }
                 Err(e) => {
-                    tracing::error!("mqtt eventloop error: {e}; retrying in 2s");
-                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    metrics::MQTT_RECONNECTS.inc();
+                    mqtt_state.connection.on_error(&e);
+                    let delay = backoff.next_delay();
+                    tracing::error!("mqtt eventloop error: {e}; retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
                 }
             }
         }
@@ -605,13 +658,6 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn ensure_trailing_slash(mut value: String) -> String {
-    if !value.ends_with('/') {
-        value.push('/');
-    }
-    value
-}
-
 #[cfg(unix)]
 async fn shutdown_signal() {
     use tokio::signal::unix::{SignalKind, signal};
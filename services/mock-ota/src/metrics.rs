@@ -0,0 +1,59 @@
+use prometheus::{
+    IntCounter, IntCounterVec, IntGaugeVec, register_int_counter, register_int_counter_vec,
+    register_int_gauge_vec,
+};
+use service_metrics::{RESULTS, result_label};
+use std::{collections::HashMap, sync::LazyLock};
+
+pub use service_metrics::{render, track};
+
+use crate::{JobStatus, OtaJob};
+
+pub static MQTT_PUBLISHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mqtt_publish_total",
+        "MQTT publishes handed to the client, by result (ok/error)",
+        &["result"]
+    )
+    .expect("register mqtt_publish_total")
+});
+
+pub static MQTT_RECONNECTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "mqtt_reconnects_total",
+        "MQTT event loop errors followed by a reconnect attempt"
+    )
+    .expect("register mqtt_reconnects_total")
+});
+
+pub static JOBS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!("mock_ota_jobs", "OTA jobs by status", &["status"])
+        .expect("register mock_ota_jobs")
+});
+
+/// Register every collector up front so dashboards see zeroes, not gaps.
+pub fn init() {
+    service_metrics::init();
+    LazyLock::force(&MQTT_RECONNECTS);
+    for result in RESULTS {
+        MQTT_PUBLISHES.with_label_values(&[result]);
+    }
+    set_jobs(&[]);
+}
+
+/// Refresh the job gauge from the current jobs, zeroing unused statuses.
+pub fn set_jobs<'a>(jobs: impl IntoIterator<Item = &'a OtaJob>) {
+    let mut counts: HashMap<&str, i64> = JobStatus::ALL.iter().map(|s| (s.as_str(), 0)).collect();
+    for job in jobs {
+        *counts.entry(job.status.as_str()).or_default() += 1;
+    }
+    for (status, count) in counts {
+        JOBS.with_label_values(&[status]).set(count);
+    }
+}
+
+pub fn record_publish<T, E>(result: &Result<T, E>) {
+    MQTT_PUBLISHES
+        .with_label_values(&[result_label(result)])
+        .inc();
+}
@@ -1,6 +1,9 @@
-use super::{ensure_authorized, AuthContext, TokenValidateResponse};
-use axum::{routing::post, Json, Router};
-use axum::http::{header, HeaderMap, StatusCode};
+use super::{
+    AuthContext, DeviceStatusPayload, JobStatus, OtaJob, TokenValidateResponse, ensure_authorized,
+    metrics, status_job_id,
+};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::{Json, Router, routing::post};
 use reqwest::Client;
 use std::net::SocketAddr;
 use tokio::{task::JoinHandle, time::Duration};
@@ -108,3 +111,51 @@ async fn ensure_authorized_success() {
     handle.abort();
     assert!(result.is_ok());
 }
+
+#[tokio::test]
+async fn metrics_report_jobs_by_status() {
+    let job = |status| OtaJob {
+        id: uuid::Uuid::new_v4(),
+        device_id: "device-1".into(),
+        artifact: "fw.bin".into(),
+        version: "1.0.0".into(),
+        status,
+        created_at: chrono::Utc::now(),
+        dispatched_at: None,
+        completed_at: None,
+        message: None,
+    };
+    metrics::set_jobs(&[
+        job(JobStatus::Completed),
+        job(JobStatus::Completed),
+        job(JobStatus::Failed),
+    ]);
+    let body = axum::body::to_bytes(metrics::render().await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains(r#"mock_ota_jobs{status="completed"} 2"#));
+    assert!(text.contains(r#"mock_ota_jobs{status="failed"} 1"#));
+    assert!(text.contains(r#"mock_ota_jobs{status="created"} 0"#));
+}
+
+#[test]
+fn status_job_id_falls_back_to_correlation_data() {
+    let job_id = uuid::Uuid::new_v4();
+    let status: DeviceStatusPayload =
+        serde_json::from_value(serde_json::json!({"status": "completed"})).unwrap();
+    assert_eq!(
+        status_job_id(&status, Some(job_id.to_string().as_bytes())),
+        Some(job_id)
+    );
+    assert_eq!(status_job_id(&status, None), None);
+    assert_eq!(status_job_id(&status, Some(b"job-1")), None);
+
+    let other = uuid::Uuid::new_v4();
+    let status: DeviceStatusPayload =
+        serde_json::from_value(serde_json::json!({"job_id": other, "status": "failed"})).unwrap();
+    assert_eq!(
+        status_job_id(&status, Some(job_id.to_string().as_bytes())),
+        Some(other)
+    );
+}
@@ -0,0 +1,304 @@
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+use topic_layout::TopicLayout;
+
+use crate::handlers::parse_wait;
+use crate::publisher::Publisher;
+use crate::store::{StoredMessage, is_telemetry};
+use crate::types::MetricValue;
+
+/// Resolved alerts kept for `GET /alerts`, oldest dropped first.
+const RESOLVED_HISTORY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl Op {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            ">" => Some(Self::Gt),
+            ">=" => Some(Self::Ge),
+            "<" => Some(Self::Lt),
+            "<=" => Some(Self::Le),
+            "==" => Some(Self::Eq),
+            "!=" => Some(Self::Ne),
+            _ => None,
+        }
+    }
+
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Gt => value > threshold,
+            Self::Ge => value >= threshold,
+            Self::Lt => value < threshold,
+            Self::Le => value <= threshold,
+            Self::Eq => value == threshold,
+            Self::Ne => value != threshold,
+        }
+    }
+}
+
+/// `[name:] <metric> <op> <threshold> [for <duration>] [clear <threshold>]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub metric: String,
+    pub op: Op,
+    pub threshold: f64,
+    /// How long the condition must hold before the alert fires.
+    pub hold: Duration,
+    /// Hysteresis: an active alert only resolves once the value is back past
+    /// this level (`<` it for `>`/`>=` rules, `>` it for `<`/`<=` rules).
+    pub clear: Option<f64>,
+}
+
+impl Rule {
+    pub fn parse(line: &str) -> Result<Self> {
+        let (name, expr) = match line.split_once(':') {
+            Some((name, expr)) => (Some(name.trim()), expr.trim()),
+            None => (None, line.trim()),
+        };
+        let tokens: Vec<&str> = expr.split_whitespace().collect();
+        let [metric, op, threshold, rest @ ..] = tokens.as_slice() else {
+            bail!("expected '<metric> <op> <threshold>'");
+        };
+        let op = Op::parse(op).ok_or_else(|| anyhow!("unknown operator '{op}'"))?;
+        let threshold: f64 = threshold
+            .parse()
+            .with_context(|| format!("invalid threshold '{threshold}'"))?;
+        let mut rule = Self {
+            name: name.unwrap_or(expr).to_string(),
+            metric: metric.to_string(),
+            op,
+            threshold,
+            hold: Duration::ZERO,
+            clear: None,
+        };
+        let mut rest = rest.iter();
+        while let Some(keyword) = rest.next() {
+            let value = rest
+                .next()
+                .ok_or_else(|| anyhow!("'{keyword}' needs a value"))?;
+            match *keyword {
+                "for" => {
+                    rule.hold =
+                        parse_wait(value).ok_or_else(|| anyhow!("invalid duration '{value}'"))?;
+                }
+                "clear" => {
+                    if matches!(op, Op::Eq | Op::Ne) {
+                        bail!("'clear' needs a <, <=, > or >= rule");
+                    }
+                    rule.clear = Some(
+                        value
+                            .parse()
+                            .with_context(|| format!("invalid clear level '{value}'"))?,
+                    );
+                }
+                other => bail!("unexpected '{other}'"),
+            }
+        }
+        Ok(rule)
+    }
+
+    fn cleared(&self, value: f64) -> bool {
+        match (self.clear, self.op) {
+            (Some(clear), Op::Gt | Op::Ge) => value < clear,
+            (Some(clear), Op::Lt | Op::Le) => value > clear,
+            _ => !self.op.holds(value, self.threshold),
+        }
+    }
+}
+
+/// Parse a rules file: one rule per line, `#` starts a comment.
+pub fn parse_rules(text: &str) -> Result<Vec<Rule>> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| Rule::parse(line).with_context(|| format!("rule on line {}", i + 1)))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Active,
+    Resolved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub id: u64,
+    pub rule: String,
+    pub device_id: String,
+    pub metric: String,
+    pub status: AlertStatus,
+    pub threshold: f64,
+    /// Value that triggered the alert.
+    pub value: f64,
+    /// Most recent value seen while active.
+    pub last_value: f64,
+    pub triggered_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct AlertState {
+    next_id: u64,
+    /// When the condition started holding, per (rule index, device).
+    pending: HashMap<(usize, String), DateTime<Utc>>,
+    active: BTreeMap<(usize, String), Alert>,
+    resolved: VecDeque<Alert>,
+}
+
+/// Evaluates rules against readings; each breach episode produces exactly one
+/// `active` and one `resolved` transition per rule and device.
+#[derive(Default)]
+pub struct AlertEngine {
+    rules: Vec<Rule>,
+    state: Mutex<AlertState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Self {
+            rules,
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read alert rules {}", path.display()))?;
+        Ok(Self::new(parse_rules(&text)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Apply one reading; returns the alerts that changed state.
+    pub fn evaluate(
+        &self,
+        device_id: &str,
+        metrics: &BTreeMap<String, MetricValue>,
+        at: DateTime<Utc>,
+    ) -> Vec<Alert> {
+        let mut state = self.state.lock().expect("alert state poisoned");
+        let mut changed = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Some(value) = metrics.get(&rule.metric).and_then(MetricValue::as_f64) else {
+                continue;
+            };
+            let key = (index, device_id.to_string());
+
+            if let Some(alert) = state.active.get_mut(&key) {
+                alert.last_value = value;
+                if rule.cleared(value) {
+                    let mut alert = state.active.remove(&key).expect("active alert");
+                    alert.status = AlertStatus::Resolved;
+                    alert.resolved_at = Some(at);
+                    if state.resolved.len() == RESOLVED_HISTORY {
+                        state.resolved.pop_front();
+                    }
+                    state.resolved.push_back(alert.clone());
+                    changed.push(alert);
+                }
+                continue;
+            }
+
+            if !rule.op.holds(value, rule.threshold) {
+                state.pending.remove(&key);
+                continue;
+            }
+            let since = *state.pending.entry(key.clone()).or_insert(at);
+            let held = (at - since).to_std().unwrap_or_default();
+            if held < rule.hold {
+                continue;
+            }
+            state.pending.remove(&key);
+            state.next_id += 1;
+            let alert = Alert {
+                id: state.next_id,
+                rule: rule.name.clone(),
+                device_id: device_id.to_string(),
+                metric: rule.metric.clone(),
+                status: AlertStatus::Active,
+                threshold: rule.threshold,
+                value,
+                last_value: value,
+                triggered_at: at,
+                resolved_at: None,
+            };
+            state.active.insert(key, alert.clone());
+            changed.push(alert);
+        }
+        changed
+    }
+
+    /// Active alerts, then resolved ones newest first.
+    pub fn alerts(&self) -> (Vec<Alert>, Vec<Alert>) {
+        let state = self.state.lock().expect("alert state poisoned");
+        (
+            state.active.values().cloned().collect(),
+            state.resolved.iter().rev().cloned().collect(),
+        )
+    }
+}
+
+/// Evaluate a consumed telemetry message and publish every alert that changed
+/// state to the device's alert topic, `{prefix}{device_id}/alerts` by default.
+pub fn evaluate_message(
+    engine: &AlertEngine,
+    mqtt: &Publisher,
+    topics: &TopicLayout,
+    message: &StoredMessage,
+) {
+    let Some(device_id) = &message.device_id else {
+        return;
+    };
+    // Alerts (and other device topics) are not readings
+    if engine.is_empty() || !is_telemetry(topics, &message.topic, device_id) {
+        return;
+    }
+    for reading in message.readings() {
+        let metrics = reading.resolved_metrics();
+        for alert in engine.evaluate(&reading.device_id, &metrics, message.received_at) {
+            let topic = match topics.alert_topic(&alert.device_id) {
+                Ok(topic) => topic,
+                Err(e) => {
+                    tracing::warn!("no alert topic for {}: {e}", alert.device_id);
+                    continue;
+                }
+            };
+            tracing::warn!(
+                "alert {:?} '{}' for {}: {}={}",
+                alert.status,
+                alert.rule,
+                alert.device_id,
+                alert.metric,
+                alert.last_value
+            );
+            mqtt.publish_event(topic, &alert);
+        }
+    }
+}
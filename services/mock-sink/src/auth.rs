@@ -0,0 +1,89 @@
+use axum::http::{HeaderMap, StatusCode, header};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone)]
+pub struct AuthContext {
+    pub client: Client,
+    pub validate_url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenValidateRequest<'a> {
+    access_token: &'a str,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TokenValidateResponse {
+    pub valid: bool,
+    pub service: Option<String>,
+    pub device_id: Option<String>,
+}
+
+/// Validate the device bearer token and return the device it was issued to.
+pub async fn authenticate_device(
+    auth: &AuthContext,
+    headers: &HeaderMap,
+) -> Result<String, (StatusCode, String)> {
+    let auth_header = headers.get(header::AUTHORIZATION).ok_or((
+        StatusCode::UNAUTHORIZED,
+        "missing authorization header".into(),
+    ))?;
+    let auth_str = auth_header.to_str().map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            "invalid authorization header".into(),
+        )
+    })?;
+    let token = auth_str
+        .strip_prefix("Bearer ")
+        .or_else(|| auth_str.strip_prefix("bearer "))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .ok_or((StatusCode::UNAUTHORIZED, "invalid bearer token".into()))?;
+
+    let response = auth
+        .client
+        .post(&auth.validate_url)
+        .json(&TokenValidateRequest {
+            access_token: token,
+        })
+        .send()
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "auth validate request failed");
+            (StatusCode::BAD_GATEWAY, "auth service unavailable".into())
+        })?;
+
+    if !response.status().is_success() {
+        tracing::warn!(status = %response.status(), "auth validate returned non-success");
+        return Err((StatusCode::UNAUTHORIZED, "token validation failed".into()));
+    }
+
+    let body = response
+        .json::<TokenValidateResponse>()
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "failed to decode auth validate response");
+            (StatusCode::BAD_GATEWAY, "invalid auth response".into())
+        })?;
+
+    if !body.valid {
+        return Err((StatusCode::UNAUTHORIZED, "invalid token".into()));
+    }
+
+    body.device_id
+        .ok_or((StatusCode::FORBIDDEN, "token is not a device token".into()))
+}
+
+/// Reject telemetry for a device other than the one the token was issued to.
+pub fn ensure_same_device(token_device: &str, device_id: &str) -> Result<(), (StatusCode, String)> {
+    if token_device == device_id {
+        return Ok(());
+    }
+    tracing::warn!(%token_device, %device_id, "telemetry device_id does not match token");
+    Err((
+        StatusCode::FORBIDDEN,
+        "token not issued for this device_id".into(),
+    ))
+}
@@ -0,0 +1,247 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+use crate::senml;
+
+/// Wire encodings accepted for telemetry payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFormat {
+    Json,
+    Cbor,
+    MsgPack,
+    /// SenML (RFC 8428) pack in JSON, resolved into telemetry documents.
+    SenmlJson,
+    /// SenML pack in CBOR (integer labels), resolved like [`Self::SenmlJson`].
+    SenmlCbor,
+}
+
+impl PayloadFormat {
+    pub const ALL: [Self; 5] = [
+        Self::Json,
+        Self::Cbor,
+        Self::MsgPack,
+        Self::SenmlJson,
+        Self::SenmlCbor,
+    ];
+
+    /// Map a `Content-Type` (parameters ignored) to a format.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" | "text/json" => Some(Self::Json),
+            "application/cbor" => Some(Self::Cbor),
+            "application/senml+json" => Some(Self::SenmlJson),
+            "application/senml+cbor" => Some(Self::SenmlCbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MsgPack)
+            }
+            _ => None,
+        }
+    }
+
+    /// Format implied by a trailing `/cbor`, `/msgpack`, `/senml` or
+    /// `/senml-cbor` topic level (JSON otherwise).
+    pub fn from_topic(topic: &str) -> Self {
+        topic
+            .rsplit('/')
+            .next()
+            .and_then(Self::from_name)
+            .unwrap_or(Self::Json)
+    }
+
+    /// Content type of a consumed message: the MQTT v5 content-type property
+    /// when present and recognised, otherwise the topic suffix.
+    pub fn resolve(topic: &str, content_type: Option<&str>) -> Self {
+        content_type
+            .and_then(Self::from_content_type)
+            .unwrap_or_else(|| Self::from_topic(topic))
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Self::Json),
+            "cbor" => Some(Self::Cbor),
+            "msgpack" => Some(Self::MsgPack),
+            "senml" => Some(Self::SenmlJson),
+            "senml-cbor" => Some(Self::SenmlCbor),
+            _ => None,
+        }
+    }
+
+    /// Topic level appended when forwarding this encoding.
+    pub fn topic_suffix(self) -> Option<&'static str> {
+        match self {
+            Self::Json => None,
+            other => Some(other.name()),
+        }
+    }
+
+    /// `topic` without a trailing encoding level such as `/cbor`, so anything
+    /// keyed by the telemetry topic matches every encoding.
+    pub fn canonical_topic(topic: &str) -> &str {
+        match topic.rsplit_once('/') {
+            Some((base, suffix)) if Self::ALL.iter().any(|f| f.topic_suffix() == Some(suffix)) => {
+                base
+            }
+            _ => topic,
+        }
+    }
+
+    pub fn is_senml(self) -> bool {
+        matches!(self, Self::SenmlJson | Self::SenmlCbor)
+    }
+
+    /// Plain encoding SenML payloads are carried in (and re-encoded with once
+    /// resolved).
+    pub fn carrier(self) -> Self {
+        match self {
+            Self::SenmlJson => Self::Json,
+            Self::SenmlCbor => Self::Cbor,
+            other => other,
+        }
+    }
+
+    /// MIME type, as sent in the MQTT v5 content-type property.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Cbor => "application/cbor",
+            Self::MsgPack => "application/msgpack",
+            Self::SenmlJson => "application/senml+json",
+            Self::SenmlCbor => "application/senml+cbor",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Cbor => "cbor",
+            Self::MsgPack => "msgpack",
+            Self::SenmlJson => "senml",
+            Self::SenmlCbor => "senml-cbor",
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Json | Self::SenmlJson => Ok(serde_json::to_vec(value)?),
+            Self::Cbor | Self::SenmlCbor => {
+                let mut out = Vec::new();
+                ciborium::into_writer(value, &mut out)?;
+                Ok(out)
+            }
+            Self::MsgPack => Ok(rmp_serde::to_vec_named(value)?),
+        }
+    }
+
+    /// Decode to JSON; SenML packs become an array of telemetry documents.
+    pub fn decode(self, payload: &[u8]) -> Result<Value> {
+        self.decode_at(payload, Utc::now())
+    }
+
+    /// Like [`decode`](Self::decode) for a payload received at `received_at`,
+    /// which SenML records with relative times are resolved against.
+    pub fn decode_at(self, payload: &[u8], received_at: DateTime<Utc>) -> Result<Value> {
+        match self {
+            Self::SenmlJson | Self::SenmlCbor => {
+                let pack = self.carrier().decode(payload)?;
+                let records = senml::resolve(pack, received_at)?;
+                Ok(Value::Array(senml::to_telemetry(records)))
+            }
+            Self::Json => Ok(serde_json::from_slice(payload)?),
+            Self::Cbor => {
+                let value: ciborium::Value = ciborium::from_reader(payload)?;
+                cbor_to_json(value)
+            }
+            Self::MsgPack => {
+                let value = rmpv::decode::read_value(&mut &payload[..])?;
+                msgpack_to_json(value)
+            }
+        }
+    }
+}
+
+fn hex_string(bytes: &[u8]) -> Value {
+    Value::String(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+fn float_to_json(f: f64) -> Result<Value> {
+    Number::from_f64(f)
+        .map(Value::Number)
+        .ok_or_else(|| anyhow!("non-finite number {f}"))
+}
+
+/// Map keys become strings (integer keys are stringified) and byte strings hex.
+fn cbor_to_json(value: ciborium::Value) -> Result<Value> {
+    use ciborium::Value as C;
+    Ok(match value {
+        C::Null => Value::Null,
+        C::Bool(b) => Value::Bool(b),
+        C::Integer(i) => {
+            let i = i128::from(i);
+            if let Ok(n) = i64::try_from(i) {
+                Value::from(n)
+            } else if let Ok(n) = u64::try_from(i) {
+                Value::from(n)
+            } else {
+                return Err(anyhow!("integer {i} out of range"));
+            }
+        }
+        C::Float(f) => float_to_json(f)?,
+        C::Text(s) => Value::String(s),
+        C::Bytes(b) => hex_string(&b),
+        C::Tag(_, inner) => cbor_to_json(*inner)?,
+        C::Array(items) => {
+            Value::Array(items.into_iter().map(cbor_to_json).collect::<Result<_>>()?)
+        }
+        C::Map(entries) => {
+            let mut map = Map::new();
+            for (key, value) in entries {
+                let key = match cbor_to_json(key)? {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                map.insert(key, cbor_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        other => return Err(anyhow!("unsupported CBOR value {other:?}")),
+    })
+}
+
+fn msgpack_to_json(value: rmpv::Value) -> Result<Value> {
+    use rmpv::Value as M;
+    Ok(match value {
+        M::Nil => Value::Null,
+        M::Boolean(b) => Value::Bool(b),
+        M::Integer(i) => match (i.as_i64(), i.as_u64()) {
+            (Some(n), _) => Value::from(n),
+            (None, Some(n)) => Value::from(n),
+            _ => return Err(anyhow!("integer out of range")),
+        },
+        M::F32(f) => float_to_json(f64::from(f))?,
+        M::F64(f) => float_to_json(f)?,
+        M::String(s) => Value::String(s.into_str().ok_or_else(|| anyhow!("string is not UTF-8"))?),
+        M::Binary(b) => hex_string(&b),
+        M::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(msgpack_to_json)
+                .collect::<Result<_>>()?,
+        ),
+        M::Map(entries) => {
+            let mut map = Map::new();
+            for (key, value) in entries {
+                let key = match msgpack_to_json(key)? {
+                    Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                map.insert(key, msgpack_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        M::Ext(_, b) => hex_string(&b),
+    })
+}
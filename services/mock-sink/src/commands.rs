@@ -0,0 +1,379 @@
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use mqtt_client::Properties;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::codec::PayloadFormat;
+use crate::handlers::{AppState, parse_wait};
+
+const MAX_COMMAND_WAIT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandStatus {
+    Pending,
+    Acked,
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+impl CommandStatus {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Succeeded | Self::Failed | Self::TimedOut)
+    }
+
+    fn from_reply(status: &str) -> Option<Self> {
+        match status {
+            "ack" | "acked" | "accepted" | "received" => Some(Self::Acked),
+            "success" | "succeeded" | "ok" | "done" | "completed" => Some(Self::Succeeded),
+            "failed" | "failure" | "error" | "rejected" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Command {
+    pub correlation_id: Uuid,
+    pub device_id: String,
+    pub command: String,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+    pub status: CommandStatus,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acked_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+// Body of POST /devices/{device_id}/commands
+#[derive(Debug, Deserialize)]
+pub struct CommandRequest {
+    pub command: String,
+    #[serde(default)]
+    pub params: Value,
+    /// How long the device has to finish, e.g. `30s`; defaults to
+    /// `MOCK_SINK_COMMAND_TIMEOUT_SECS`.
+    pub timeout: Option<String>,
+}
+
+// Published on the commands topic, {prefix}{device_id}/commands by default
+#[derive(Debug, Serialize)]
+struct CommandMessage<'a> {
+    correlation_id: Uuid,
+    command: &'a str,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    params: &'a Value,
+    expires_at: DateTime<Utc>,
+}
+
+// Device reply on the commands topic plus /response
+#[derive(Debug, Deserialize)]
+struct CommandReply {
+    /// Falls back to the MQTT v5 correlation data.
+    correlation_id: Option<Uuid>,
+    status: String,
+    result: Option<Value>,
+    message: Option<String>,
+}
+
+// Query string for command endpoints that can block
+#[derive(Debug, Deserialize)]
+pub struct WaitParams {
+    /// Block until the command finishes or this long (`500ms`, `30s`, `2m`).
+    pub wait: Option<String>,
+}
+
+/// Commands sent to devices, keyed by correlation id.
+pub struct CommandTracker {
+    pub default_timeout: Duration,
+    // How long finished commands stay queryable; `None` keeps them forever
+    retention: Option<Duration>,
+    commands: Mutex<HashMap<Uuid, watch::Sender<Command>>>,
+}
+
+impl CommandTracker {
+    pub fn new(default_timeout: Duration, retention: Option<Duration>) -> Self {
+        Self {
+            default_timeout,
+            retention,
+            commands: Mutex::default(),
+        }
+    }
+
+    pub fn insert(&self, command: Command) {
+        self.prune(Utc::now());
+        let (tx, _) = watch::channel(command.clone());
+        self.commands
+            .lock()
+            .expect("commands poisoned")
+            .insert(command.correlation_id, tx);
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Command)) -> Option<Command> {
+        let commands = self.commands.lock().expect("commands poisoned");
+        let tx = commands.get(&id)?;
+        tx.send_modify(f);
+        Some(tx.borrow().clone())
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Command> {
+        let commands = self.commands.lock().expect("commands poisoned");
+        commands.get(&id).map(|tx| tx.borrow().clone())
+    }
+
+    pub fn list(&self, device_id: &str) -> Vec<Command> {
+        let commands = self.commands.lock().expect("commands poisoned");
+        let mut list: Vec<Command> = commands
+            .values()
+            .map(|tx| tx.borrow().clone())
+            .filter(|c| c.device_id == device_id)
+            .collect();
+        list.sort_by_key(|c| c.created_at);
+        list
+    }
+
+    /// Mark the command timed out unless the device already finished it;
+    /// returns whether it did time out.
+    pub fn expire(&self, id: Uuid, now: DateTime<Utc>) -> bool {
+        let mut expired = false;
+        self.update(id, |c| {
+            if !c.status.is_terminal() {
+                c.status = CommandStatus::TimedOut;
+                c.completed_at = Some(now);
+                expired = true;
+            }
+        });
+        expired
+    }
+
+    /// Forget commands that finished more than the retention window before
+    /// `now`; returns how many were dropped.
+    pub fn prune(&self, now: DateTime<Utc>) -> usize {
+        let Some(retention) = self
+            .retention
+            .and_then(|r| chrono::Duration::from_std(r).ok())
+        else {
+            return 0;
+        };
+        let mut commands = self.commands.lock().expect("commands poisoned");
+        let before = commands.len();
+        commands.retain(|_, tx| {
+            let command = tx.borrow();
+            !(command.status.is_terminal()
+                && command.completed_at.is_some_and(|at| at + retention <= now))
+        });
+        before - commands.len()
+    }
+
+    /// Apply a device reply from `{device_id}/commands/response`;
+    /// `correlation_data` is the reply's MQTT v5 property, if any.
+    pub fn handle_reply(
+        &self,
+        device_id: &str,
+        payload: &Value,
+        correlation_data: Option<&[u8]>,
+    ) -> Option<Command> {
+        let reply: CommandReply = match serde_json::from_value(payload.clone()) {
+            Ok(reply) => reply,
+            Err(e) => {
+                tracing::warn!("invalid command reply from {device_id}: {e}");
+                return None;
+            }
+        };
+        let Some(correlation_id) = reply
+            .correlation_id
+            .or_else(|| std::str::from_utf8(correlation_data?).ok()?.parse().ok())
+        else {
+            tracing::warn!("command reply from {device_id} has no correlation id");
+            return None;
+        };
+        let Some(status) = CommandStatus::from_reply(&reply.status.to_ascii_lowercase()) else {
+            tracing::warn!("unknown command reply status '{}'", reply.status);
+            return None;
+        };
+        let now = Utc::now();
+        let updated = self.update(correlation_id, |c| {
+            if c.device_id != device_id {
+                tracing::warn!(
+                    "{device_id} replied to command {} for {}",
+                    c.correlation_id,
+                    c.device_id
+                );
+                return;
+            }
+            if c.status.is_terminal() {
+                tracing::info!("late reply for command {} ignored", c.correlation_id);
+                return;
+            }
+            c.status = status;
+            if status == CommandStatus::Acked {
+                c.acked_at = Some(now);
+            } else {
+                c.completed_at = Some(now);
+            }
+            if reply.result.is_some() {
+                c.result = reply.result;
+            }
+            if reply.message.is_some() {
+                c.message = reply.message;
+            }
+        });
+        if updated.is_none() {
+            tracing::info!("reply for unknown command {correlation_id}");
+        }
+        updated
+    }
+
+    /// Wait until the command finishes or `wait` elapses, returning its state.
+    pub async fn wait(&self, id: Uuid, wait: Duration) -> Option<Command> {
+        let mut rx = {
+            let commands = self.commands.lock().expect("commands poisoned");
+            commands.get(&id)?.subscribe()
+        };
+        let _ = tokio::time::timeout(wait, rx.wait_for(|c| c.status.is_terminal())).await;
+        let command = rx.borrow().clone();
+        Some(command)
+    }
+}
+
+fn parse_wait_param(raw: Option<&str>) -> Result<Option<Duration>, (StatusCode, String)> {
+    raw.map(|raw| {
+        parse_wait(raw)
+            .map(|d| d.min(MAX_COMMAND_WAIT))
+            .ok_or((StatusCode::BAD_REQUEST, format!("invalid wait '{raw}'")))
+    })
+    .transpose()
+}
+
+fn command_response(command: Command) -> (StatusCode, Json<Command>) {
+    let status = if command.status.is_terminal() {
+        StatusCode::OK
+    } else {
+        StatusCode::ACCEPTED
+    };
+    (status, Json(command))
+}
+
+pub async fn send_command(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Query(params): Query<WaitParams>,
+    Json(req): Json<CommandRequest>,
+) -> Result<(StatusCode, Json<Command>), (StatusCode, String)> {
+    let wait = parse_wait_param(params.wait.as_deref())?;
+    if req.command.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "command must not be empty".into()));
+    }
+    let timeout = match req.timeout.as_deref() {
+        Some(raw) => parse_wait(raw)
+            .filter(|d| !d.is_zero())
+            .ok_or((StatusCode::BAD_REQUEST, format!("invalid timeout '{raw}'")))?,
+        None => state.commands.default_timeout,
+    };
+
+    let now = Utc::now();
+    let command = Command {
+        correlation_id: Uuid::new_v4(),
+        device_id: device_id.clone(),
+        command: req.command,
+        params: req.params,
+        status: CommandStatus::Pending,
+        created_at: now,
+        expires_at: now + chrono::Duration::from_std(timeout).unwrap_or_default(),
+        acked_at: None,
+        completed_at: None,
+        result: None,
+        message: None,
+    };
+    let payload = serde_json::to_vec(&CommandMessage {
+        correlation_id: command.correlation_id,
+        command: &command.command,
+        params: &command.params,
+        expires_at: command.expires_at,
+    })
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let topic = state
+        .topics
+        .command_topic(&device_id)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    // MQTT v5 devices can reply via the response topic and correlation data,
+    // and the broker drops the command once it has timed out
+    let properties = Properties {
+        content_type: Some(PayloadFormat::Json.content_type().into()),
+        response_topic: state.topics.command_response_topic(&device_id).ok(),
+        correlation_data: Some(command.correlation_id.to_string().into_bytes()),
+        user_properties: Vec::new(),
+        message_expiry: Some(timeout.as_secs_f64().ceil() as u32),
+    };
+
+    // Track before publishing so a fast reply is never missed
+    let id = command.correlation_id;
+    state.commands.insert(command.clone());
+    if let Err(e) = state.mqtt.publish(topic.clone(), payload, properties).await {
+        state.commands.update(id, |c| {
+            c.status = CommandStatus::Failed;
+            c.completed_at = Some(Utc::now());
+            c.message = Some("publish failed".into());
+        });
+        return Err((StatusCode::BAD_GATEWAY, format!("mqtt publish failed: {e}")));
+    }
+    tracing::info!(correlation_id = %id, %topic, command = %command.command, "command published");
+
+    let tracker = Arc::clone(&state.commands);
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let now = Utc::now();
+        if tracker.expire(id, now) {
+            tracing::warn!(correlation_id = %id, %device_id, "command timed out");
+        }
+        tracker.prune(now);
+    });
+
+    let command = match wait {
+        Some(wait) => state.commands.wait(id, wait).await.unwrap_or(command),
+        None => command,
+    };
+    Ok(command_response(command))
+}
+
+pub async fn list_commands(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+) -> Json<Vec<Command>> {
+    Json(state.commands.list(&device_id))
+}
+
+pub async fn get_command(
+    State(state): State<Arc<AppState>>,
+    Path((device_id, id)): Path<(String, Uuid)>,
+    Query(params): Query<WaitParams>,
+) -> Result<(StatusCode, Json<Command>), (StatusCode, String)> {
+    let wait = parse_wait_param(params.wait.as_deref())?;
+    let command = match wait {
+        Some(wait) => state.commands.wait(id, wait).await,
+        None => state.commands.get(id),
+    }
+    .filter(|c| c.device_id == device_id)
+    .ok_or((StatusCode::NOT_FOUND, "command not found".into()))?;
+    Ok(command_response(command))
+}
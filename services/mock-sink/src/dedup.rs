@@ -0,0 +1,83 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use topic_layout::TopicLayout;
+
+use crate::codec::PayloadFormat;
+use crate::store::is_telemetry;
+use crate::types::TelemetryIn;
+
+#[derive(Default)]
+struct Seen {
+    claimed_at: HashMap<String, Instant>,
+    /// Claim order, oldest first, for expiring keys.
+    order: VecDeque<(Instant, String)>,
+}
+
+/// Keys of readings accepted within the last `window`; `None` disables
+/// deduplication.
+pub struct Deduplicator {
+    window: Option<Duration>,
+    seen: Mutex<Seen>,
+}
+
+impl Deduplicator {
+    pub fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            seen: Mutex::default(),
+        }
+    }
+
+    /// Record `key`; false when it was already claimed within the window.
+    pub fn claim(&self, key: &str, now: Instant) -> bool {
+        let Some(window) = self.window else {
+            return true;
+        };
+        let mut seen = self.seen.lock().expect("dedup mutex poisoned");
+        while let Some((at, _)) = seen.order.front()
+            && now.duration_since(*at) > window
+        {
+            let (at, key) = seen.order.pop_front().expect("front checked");
+            // Released and re-claimed keys have a newer entry
+            if seen.claimed_at.get(&key) == Some(&at) {
+                seen.claimed_at.remove(&key);
+            }
+        }
+        if seen.claimed_at.contains_key(key) {
+            return false;
+        }
+        seen.claimed_at.insert(key.to_string(), now);
+        seen.order.push_back((now, key.to_string()));
+        true
+    }
+
+    /// Forget a claim whose reading was not accepted after all, so a retry
+    /// goes through.
+    pub fn release(&self, key: &str) {
+        self.seen
+            .lock()
+            .expect("dedup mutex poisoned")
+            .claimed_at
+            .remove(key);
+    }
+}
+
+/// Reading key of a consumed telemetry message, when it carries a `seq`.
+pub fn consumed_key(
+    topics: &TopicLayout,
+    device_id: &str,
+    topic: &str,
+    format: PayloadFormat,
+    payload: &[u8],
+) -> Option<String> {
+    if !is_telemetry(topics, topic, device_id) {
+        return None;
+    }
+    let value = format.decode(payload).ok()?;
+    serde_json::from_value::<TelemetryIn>(value)
+        .ok()?
+        .dedup_key()
+}
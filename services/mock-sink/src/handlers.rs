@@ -1,62 +1,906 @@
 use axum::{
     Json,
-    extract::State,
-    http::{HeaderMap, StatusCode},
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
 };
-use rumqttc::{AsyncClient, QoS};
+use chrono::{DateTime, Utc};
+use mqtt_client::{ConnectionState, ConnectionStatus};
 use serde_json::Value;
-use std::sync::Arc;
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use topic_layout::TopicLayout;
 
-use crate::types::{TelemetryIn, TelemetryResp};
+use crate::alerts::{Alert, AlertEngine, AlertStatus};
+use crate::auth::{AuthContext, authenticate_device, ensure_same_device};
+use crate::codec::PayloadFormat;
+use crate::commands::CommandTracker;
+use crate::dedup::Deduplicator;
+use crate::metrics::{self, DeviceGauges};
+use crate::outbox::Outbox;
+use crate::overload::{Limiter, Permit, RETRY_AFTER};
+use crate::presence::{Presence, PresenceTracker};
+use crate::publisher::{Publisher, telemetry_properties};
+use crate::schema::{SchemaRegistry, Violation};
+use crate::senml;
+use crate::shadow::ShadowStore;
+use crate::store::{
+    DeviceSummary, MessageQuery, ReceivedQuery, Store, StoredMessage, is_telemetry,
+};
+use crate::types::{
+    AlertParams, BatchItemResult, BatchResp, Delivery, InvalidParams, LatestParams, ReceivedParams,
+    TelemetryIn, TelemetryQuery, TelemetryResp,
+};
+
+const DEFAULT_QUERY_LIMIT: u32 = 100;
+const MAX_QUERY_LIMIT: u32 = 1000;
+const MAX_RECEIVED_WAIT: Duration = Duration::from_secs(300);
 
 #[derive(Clone)]
 pub struct AppState {
-    pub mqtt: AsyncClient,
-    pub topic_prefix: String,
+    pub mqtt: Publisher,
+    pub topics: TopicLayout,
+    /// `None` when anonymous telemetry is allowed.
+    pub auth: Option<AuthContext>,
+    pub store: Store,
+    /// Every consumed message, after it has been stored.
+    pub events: broadcast::Sender<StoredMessage>,
+    pub schemas: Arc<SchemaRegistry>,
+    pub batch_max_items: usize,
+    pub alerts: Arc<AlertEngine>,
+    pub presence: Arc<PresenceTracker>,
+    pub commands: Arc<CommandTracker>,
+    pub shadows: Arc<ShadowStore>,
+    pub device_gauges: Arc<DeviceGauges>,
+    pub outbox: Arc<Outbox>,
+    /// Wait for the broker's PubAck before answering `POST /telemetry`
+    /// unless the request's `X-Publish-Confirm` header says otherwise.
+    pub publish_confirm: bool,
+    pub publish_confirm_timeout: Duration,
+    /// Caps concurrent `POST /telemetry` and `/telemetry/batch` requests.
+    pub limiter: Arc<Limiter>,
+    /// `Idempotency-Key`s and reading keys accepted over HTTP.
+    pub dedup: Arc<Deduplicator>,
+    /// Broker connection as seen by the MQTT event loop.
+    pub connection: ConnectionState,
+}
+
+impl AppState {
+    /// Stored topics that carry telemetry readings for `device_id`.
+    async fn telemetry_topics(&self, device_id: &str) -> Result<Vec<String>, (StatusCode, String)> {
+        let topics = self
+            .store
+            .device_topics(device_id)
+            .await
+            .map_err(store_error)?;
+        Ok(topics
+            .into_iter()
+            .filter(|topic| is_telemetry(&self.topics, topic, device_id))
+            .collect())
+    }
+}
+
+fn store_error(e: anyhow::Error) -> (StatusCode, String) {
+    tracing::error!(error = %e, "store query failed");
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "store query failed".into(),
+    )
+}
+
+pub async fn health(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let stored = state.store.count().await.ok();
+    let invalid = state.store.count_invalid().await.ok();
+    Json(serde_json::json!({
+        "status": "healthy",
+        "stored_messages": stored,
+        "invalid_messages": invalid,
+        "mqtt_connected": state.connection.is_connected(),
+        "mqtt": connection_json(&state.connection.status()),
+        "outbox_depth": state.outbox.depth(),
+        "telemetry_inflight": state.limiter.in_flight(),
+        "telemetry_inflight_limit": state.limiter.limit(),
+    }))
+}
+
+fn connection_json(status: &ConnectionStatus) -> Value {
+    serde_json::json!({
+        "connected": status.connected,
+        "connected_since": status.connected_since.map(DateTime::<Utc>::from),
+        "failures": status.failures,
+        "last_error": status.last_error,
+    })
+}
+
+/// Why a single telemetry item was not accepted.
+#[derive(Debug)]
+pub struct IngestError {
+    pub status: StatusCode,
+    pub error: String,
+    pub violations: Vec<Violation>,
+    /// Sent as `Retry-After` when the client should back off.
+    pub retry_after: Option<Duration>,
+}
+
+impl IngestError {
+    fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+            violations: Vec::new(),
+            retry_after: None,
+        }
+    }
+
+    fn into_item_result(self, index: usize) -> BatchItemResult {
+        BatchItemResult {
+            index,
+            status: "error",
+            forwarded_topic: None,
+            code: Some(self.status.as_u16()),
+            error: Some(self.error),
+            violations: self.violations,
+        }
+    }
+}
+
+impl From<(StatusCode, String)> for IngestError {
+    fn from((status, error): (StatusCode, String)) -> Self {
+        Self::new(status, error)
+    }
+}
+
+impl IntoResponse for IngestError {
+    fn into_response(self) -> Response {
+        if let Some(retry_after) = self.retry_after {
+            let secs = retry_after.as_secs().max(1).to_string();
+            return (self.status, [(header::RETRY_AFTER, secs)], self.error).into_response();
+        }
+        if self.violations.is_empty() {
+            return (self.status, self.error).into_response();
+        }
+        (
+            self.status,
+            Json(serde_json::json!({
+                "error": self.error,
+                "violations": self.violations,
+            })),
+        )
+            .into_response()
+    }
+}
+
+/// Take a forwarding slot, or fail with 503 and `Retry-After` when the
+/// in-flight limit stays reached.
+async fn admit<'a>(state: &'a AppState, request_id: &str) -> Result<Permit<'a>, IngestError> {
+    state.limiter.acquire().await.ok_or_else(|| {
+        metrics::OVERLOAD_REJECTIONS.inc();
+        tracing::warn!(%request_id, limit = state.limiter.limit(), "telemetry rejected: too many requests in flight");
+        IngestError {
+            retry_after: Some(RETRY_AFTER),
+            ..IngestError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "too many telemetry requests in flight",
+            )
+        }
+    })
+}
+
+/// A validated reading ready to publish.
+struct Prepared {
+    topic: String,
+    payload: Vec<u8>,
+    dedup_key: Option<String>,
+}
+
+/// `Idempotency-Key`, scoped to the token's device so clients cannot collide.
+fn idempotency_key(headers: &HeaderMap, token_device: Option<&str>) -> Option<String> {
+    let key = headers.get("idempotency-key")?.to_str().ok()?.trim();
+    (!key.is_empty()).then(|| format!("idempotency:{}:{key}", token_device.unwrap_or("-")))
+}
+
+/// Which items are new within the dedup window: all or none for a request
+/// with an `Idempotency-Key`, otherwise per `(device_id, ts, seq)`. Also
+/// returns the claimed keys, to release if forwarding fails.
+fn claim_new(
+    state: &AppState,
+    request_id: &str,
+    request_key: Option<&str>,
+    items: &[Prepared],
+) -> (Vec<bool>, Vec<String>) {
+    let now = std::time::Instant::now();
+    let mut claimed = Vec::new();
+    let fresh: Vec<bool> = match request_key {
+        Some(key) if state.dedup.claim(key, now) => {
+            claimed.push(key.to_string());
+            vec![true; items.len()]
+        }
+        Some(_) => vec![false; items.len()],
+        None => items
+            .iter()
+            .map(|item| match &item.dedup_key {
+                Some(key) if state.dedup.claim(key, now) => {
+                    claimed.push(key.clone());
+                    true
+                }
+                Some(_) => false,
+                None => true,
+            })
+            .collect(),
+    };
+    let duplicates = fresh.iter().filter(|f| !**f).count();
+    if duplicates > 0 {
+        metrics::DUPLICATES
+            .with_label_values(&["http"])
+            .inc_by(duplicates as u64);
+        tracing::info!(%request_id, duplicates, "duplicate telemetry acknowledged without publishing");
+    }
+    (fresh, claimed)
+}
+
+/// Forward new readings; claims are released unless the readings were
+/// accepted, which a confirmation timeout still counts as.
+async fn forward_claimed(
+    state: &AppState,
+    request_id: &str,
+    items: Vec<Prepared>,
+    confirm: Option<Duration>,
+    claimed: Vec<String>,
+) -> Result<Delivery, IngestError> {
+    let result = forward(state, request_id, items, confirm).await;
+    if let Err(e) = &result
+        && e.status != StatusCode::GATEWAY_TIMEOUT
+    {
+        for key in &claimed {
+            state.dedup.release(key);
+        }
+    }
+    result
+}
+
+/// Authenticate the request once; `None` when anonymous telemetry is allowed.
+async fn token_device(
+    state: &AppState,
+    headers: &HeaderMap,
+    request_id: &str,
+) -> Result<Option<String>, IngestError> {
+    let Some(auth) = &state.auth else {
+        return Ok(None);
+    };
+    authenticate_device(auth, headers)
+        .await
+        .map(Some)
+        .map_err(|(status, reason)| {
+            tracing::warn!(%request_id, %status, %reason, "telemetry rejected");
+            IngestError::new(status, reason)
+        })
+}
+
+/// Payload format named by the request `Content-Type`; JSON when absent.
+fn request_format(headers: &HeaderMap) -> Result<PayloadFormat, IngestError> {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE) else {
+        return Ok(PayloadFormat::Json);
+    };
+    content_type
+        .to_str()
+        .ok()
+        .and_then(PayloadFormat::from_content_type)
+        .ok_or_else(|| {
+            IngestError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected application/json, application/cbor or application/msgpack",
+            )
+        })
+}
+
+fn decode_body(format: PayloadFormat, body: &[u8]) -> Result<Value, IngestError> {
+    format.decode(body).map_err(|e| {
+        IngestError::new(
+            StatusCode::BAD_REQUEST,
+            format!("invalid {} body: {e}", format.name()),
+        )
+    })
+}
+
+/// Parse, authorise and schema-check one telemetry document; it is forwarded
+/// in `format` on the matching topic.
+fn prepare(
+    state: &AppState,
+    raw: Value,
+    format: PayloadFormat,
+    token_device: Option<&str>,
+    request_id: &str,
+) -> Result<Prepared, IngestError> {
+    let body: TelemetryIn = serde_json::from_value(raw.clone()).map_err(|e| {
+        IngestError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("invalid telemetry: {e}"),
+        )
+    })?;
+
+    if let Some(token_device) = token_device {
+        ensure_same_device(token_device, &body.device_id)?;
+    }
+
+    // Template placeholders besides the device come from tags or top-level
+    // string fields, then from the configured variables
+    let mut topic = state
+        .topics
+        .telemetry_topic(&body.device_id, |name| {
+            body.tags
+                .get(name)
+                .cloned()
+                .or_else(|| body.extra.get(name)?.as_str().map(str::to_string))
+        })
+        .map_err(|e| IngestError::new(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    // Topic schemas are keyed by the telemetry topic, whatever the encoding
+    let violations = state.schemas.validate(&topic, &raw);
+    if !violations.is_empty() {
+        tracing::warn!(%request_id, device_id = %body.device_id, violations = violations.len(), "telemetry failed schema validation");
+        return Err(IngestError {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            error: "schema validation failed".into(),
+            violations,
+            retry_after: None,
+        });
+    }
+    if let Some(suffix) = format.topic_suffix() {
+        topic = format!("{topic}/{suffix}");
+    }
+
+    let payload = format.encode(&body).map_err(|e| {
+        tracing::error!(%request_id, error = %e, "serialize telemetry failed");
+        IngestError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "serialize telemetry failed",
+        )
+    })?;
+
+    let metrics = body.resolved_metrics();
+    tracing::info!(%request_id, topic = %topic, device_id = %body.device_id, metrics = ?metrics.keys().collect::<Vec<_>>(), "telemetry received");
+    Ok(Prepared {
+        topic,
+        payload,
+        dedup_key: body.dedup_key(),
+    })
+}
+
+/// Publish readings in order, or persist them in the outbox when the broker
+/// is unreachable or older readings are still queued. With `confirm`, every
+/// reading must be acknowledged by the broker within that time.
+async fn forward(
+    state: &AppState,
+    request_id: &str,
+    items: Vec<Prepared>,
+    confirm: Option<Duration>,
+) -> Result<Delivery, IngestError> {
+    let mut pending = VecDeque::from(items);
+    let mut acks = Vec::new();
+    if state.connection.is_connected() && state.outbox.depth() == 0 {
+        while let Some(item) = pending.front() {
+            let (topic, payload) = (item.topic.clone(), item.payload.clone());
+            let properties = telemetry_properties(&topic);
+            let result = match confirm {
+                Some(_) => state
+                    .mqtt
+                    .publish_confirmed(topic, payload, properties)
+                    .await
+                    .map(Some),
+                None => state
+                    .mqtt
+                    .publish(topic, payload, properties)
+                    .await
+                    .map(|()| None),
+            };
+            match result {
+                Ok(ack) => acks.extend(ack),
+                Err(e) => {
+                    tracing::warn!(%request_id, topic = %item.topic, error = %e, "mqtt publish failed; queueing");
+                    break;
+                }
+            }
+            tracing::info!(%request_id, forwarded_topic = %item.topic, "telemetry forwarded to mqtt");
+            pending.pop_front();
+        }
+    }
+    if pending.is_empty() {
+        let Some(timeout) = confirm else {
+            return Ok(Delivery::Published);
+        };
+        let deadline = tokio::time::Instant::now() + timeout;
+        for ack in acks {
+            match tokio::time::timeout_at(deadline, ack).await {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(reason))) => {
+                    tracing::warn!(%request_id, %reason, "broker rejected telemetry");
+                    return Err(IngestError::new(
+                        StatusCode::BAD_GATEWAY,
+                        format!("broker rejected the publish: {reason}"),
+                    ));
+                }
+                _ => {
+                    tracing::warn!(%request_id, ?timeout, "broker did not acknowledge telemetry");
+                    return Err(IngestError::new(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        format!(
+                            "broker did not acknowledge within {}ms",
+                            timeout.as_millis()
+                        ),
+                    ));
+                }
+            }
+        }
+        tracing::info!(%request_id, "telemetry acknowledged by broker");
+        return Ok(Delivery::Acknowledged);
+    }
+
+    let queued = pending.len();
+    state
+        .outbox
+        .enqueue(
+            pending
+                .into_iter()
+                .map(|item| (item.topic, item.payload))
+                .collect(),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(%request_id, error = %e, "outbox enqueue failed");
+            IngestError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "mqtt unavailable and outbox write failed",
+            )
+        })?;
+    tracing::info!(%request_id, queued, depth = state.outbox.depth(), "telemetry queued in outbox");
+    if confirm.is_some() {
+        return Err(IngestError::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            "mqtt unavailable; telemetry queued in outbox but not acknowledged",
+        ));
+    }
+    Ok(Delivery::Queued)
 }
 
-pub async fn health() -> Json<Value> {
-    Json(serde_json::json!({"status":"healthy"}))
+/// `X-Publish-Confirm: true|false` overrides the service default.
+fn publish_confirm(state: &AppState, headers: &HeaderMap) -> Result<Option<Duration>, IngestError> {
+    let confirm = match headers.get("x-publish-confirm") {
+        None => state.publish_confirm,
+        Some(value) => match value.to_str().map(str::trim) {
+            Ok(v) if v.eq_ignore_ascii_case("true") => true,
+            Ok(v) if v.eq_ignore_ascii_case("false") => false,
+            _ => {
+                return Err(IngestError::new(
+                    StatusCode::BAD_REQUEST,
+                    "X-Publish-Confirm must be 'true' or 'false'",
+                ));
+            }
+        },
+    };
+    Ok(confirm.then_some(state.publish_confirm_timeout))
 }
 
 pub async fn telemetry(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
-    Json(body): Json<TelemetryIn>,
-) -> Result<Json<TelemetryResp>, (StatusCode, String)> {
+    body: Bytes,
+) -> Result<Json<TelemetryResp>, IngestError> {
     let request_id = headers
         .get("x-request-id")
         .and_then(|v| v.to_str().ok())
         .unwrap_or("-");
 
-    let device_id = body.device_id.clone();
-    let topic = format!("{}{}", state.topic_prefix, device_id);
-    let payload = serde_json::to_vec(&body).map_err(|e| {
-        tracing::error!(%request_id, error = %e, "serialize telemetry failed");
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "serialize telemetry failed".to_string(),
+    let format = request_format(&headers)?;
+    let confirm = publish_confirm(&state, &headers)?;
+    let raw = decode_body(format, &body)?;
+    let token_device = token_device(&state, &headers, request_id).await?;
+
+    // A SenML pack resolves to one document per device and time; all of them
+    // must be accepted before anything is forwarded.
+    let docs = match raw {
+        Value::Array(docs) if format.is_senml() => docs
+            .into_iter()
+            .map(|doc| senml_device(doc, token_device.as_deref()))
+            .collect(),
+        doc => vec![doc],
+    };
+    let prepared = docs
+        .into_iter()
+        .map(|doc| {
+            prepare(
+                &state,
+                doc,
+                format.carrier(),
+                token_device.as_deref(),
+                request_id,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut topics: Vec<String> = Vec::new();
+    for item in &prepared {
+        if !topics.contains(&item.topic) {
+            topics.push(item.topic.clone());
+        }
+    }
+    // Only authenticated, valid readings take forwarding capacity
+    let _permit = admit(&state, request_id).await?;
+    let request_key = idempotency_key(&headers, token_device.as_deref());
+    let (fresh, claimed) = claim_new(&state, request_id, request_key.as_deref(), &prepared);
+    let prepared: Vec<Prepared> = prepared
+        .into_iter()
+        .zip(fresh)
+        .filter_map(|(item, fresh)| fresh.then_some(item))
+        .collect();
+    let delivery = if prepared.is_empty() {
+        Delivery::Duplicate
+    } else {
+        forward_claimed(&state, request_id, prepared, confirm, claimed).await?
+    };
+
+    Ok(Json(TelemetryResp {
+        status: "ok",
+        delivery,
+        forwarded_topic: topics.first().cloned().unwrap_or_default(),
+        forwarded_topics: if topics.len() > 1 { topics } else { Vec::new() },
+    }))
+}
+
+/// SenML names need not carry the device; fall back to the token's device.
+fn senml_device(mut doc: Value, token_device: Option<&str>) -> Value {
+    if let (Some(fields), Some(device_id)) = (doc.as_object_mut(), token_device) {
+        fields
+            .entry("device_id")
+            .or_insert_with(|| device_id.into());
+    }
+    doc
+}
+
+/// Split a batch body into items: an array in any supported format, or one
+/// JSON document per line for NDJSON.
+pub fn parse_batch(
+    content_type: &str,
+    body: &[u8],
+) -> Result<Vec<Result<Value, IngestError>>, IngestError> {
+    let is_ndjson = content_type
+        .split(';')
+        .next()
+        .is_some_and(|ct| matches!(ct.trim(), "application/x-ndjson" | "application/ndjson"));
+    if is_ndjson {
+        let text = std::str::from_utf8(body)
+            .map_err(|_| IngestError::new(StatusCode::BAD_REQUEST, "body is not UTF-8"))?;
+        return Ok(text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| {
+                    IngestError::new(StatusCode::BAD_REQUEST, format!("invalid JSON line: {e}"))
+                })
+            })
+            .collect());
+    }
+    let format = PayloadFormat::from_content_type(content_type).ok_or_else(|| {
+        IngestError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "expected a JSON, CBOR or MessagePack array, or NDJSON",
         )
     })?;
+    match decode_body(format, body)? {
+        Value::Array(items) => Ok(items.into_iter().map(Ok).collect()),
+        _ => Err(IngestError::new(
+            StatusCode::BAD_REQUEST,
+            "batch body must be an array or NDJSON",
+        )),
+    }
+}
 
-    tracing::info!(%request_id, topic = %topic, device_id = %body.device_id, "telemetry received");
+pub async fn telemetry_batch(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<BatchResp>, IngestError> {
+    let request_id = headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-");
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json");
 
-    let mqtt = state.mqtt.clone();
-    let publish_topic = topic.clone();
-    let publish_request_id = request_id.to_string();
-    tokio::spawn(async move {
-        if let Err(e) = mqtt
-            .publish(publish_topic.clone(), QoS::AtLeastOnce, false, payload)
-            .await
-        {
-            tracing::error!(%publish_request_id, topic = %publish_topic, error = %e, "mqtt publish failed");
+    let items = parse_batch(content_type, &body)?;
+    // NDJSON items are forwarded as JSON, SenML documents in their carrier
+    let format = PayloadFormat::from_content_type(content_type).unwrap_or(PayloadFormat::Json);
+    if items.len() > state.batch_max_items {
+        return Err(IngestError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("batch exceeds {} items", state.batch_max_items),
+        ));
+    }
+    let token_device = token_device(&state, &headers, request_id).await?;
+
+    let mut results = Vec::with_capacity(items.len());
+    let mut publish = Vec::new();
+    let mut publish_index = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        match item.and_then(|mut raw| {
+            if format.is_senml() {
+                raw = senml_device(raw, token_device.as_deref());
+            }
+            prepare(
+                &state,
+                raw,
+                format.carrier(),
+                token_device.as_deref(),
+                request_id,
+            )
+        }) {
+            Ok(prepared) => {
+                results.push(BatchItemResult {
+                    index,
+                    status: "ok",
+                    forwarded_topic: Some(prepared.topic.clone()),
+                    code: None,
+                    error: None,
+                    violations: Vec::new(),
+                });
+                publish.push(prepared);
+                publish_index.push(index);
+            }
+            Err(e) => results.push(e.into_item_result(index)),
+        }
+    }
+
+    let _permit = admit(&state, request_id).await?;
+    let request_key = idempotency_key(&headers, token_device.as_deref());
+    let (fresh, claimed) = claim_new(&state, request_id, request_key.as_deref(), &publish);
+    let valid = publish.len();
+    let mut new = Vec::with_capacity(valid);
+    for ((item, fresh), index) in publish.into_iter().zip(fresh).zip(publish_index) {
+        if fresh {
+            new.push(item);
         } else {
-            tracing::info!(%publish_request_id, forwarded_topic = %publish_topic, "telemetry forwarded to mqtt");
+            results[index].status = "duplicate";
         }
-    });
+    }
+    let accepted = new.len();
+    let duplicates = valid - accepted;
+    let rejected = results.len() - valid;
+    tracing::info!(%request_id, accepted, rejected, duplicates, "telemetry batch received");
+    let delivery = if new.is_empty() {
+        None
+    } else {
+        Some(forward_claimed(&state, request_id, new, None, claimed).await?)
+    };
 
-    Ok(Json(TelemetryResp {
-        status: "ok",
-        forwarded_topic: topic,
+    Ok(Json(BatchResp {
+        accepted,
+        rejected,
+        duplicates,
+        delivery,
+        results,
     }))
 }
+
+pub async fn list_devices(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<DeviceSummary>>, (StatusCode, String)> {
+    state.store.devices().await.map(Json).map_err(store_error)
+}
+
+/// Whether `?format=` asks for SenML; `json` (the default) returns stored messages.
+fn wants_senml(format: Option<&str>) -> Result<bool, (StatusCode, String)> {
+    match format {
+        None | Some("json") => Ok(false),
+        Some("senml") => Ok(true),
+        Some(other) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("unsupported format '{other}' (expected json or senml)"),
+        )),
+    }
+}
+
+fn senml_response(messages: &[StoredMessage]) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/senml+json")],
+        Json(senml::to_pack(messages)),
+    )
+        .into_response()
+}
+
+pub async fn device_telemetry(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Query(query): Query<TelemetryQuery>,
+) -> Result<Response, (StatusCode, String)> {
+    let senml = wants_senml(query.format.as_deref())?;
+    if let (Some(from), Some(to)) = (query.from, query.to)
+        && from > to
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "'from' must not be after 'to'".into(),
+        ));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_QUERY_LIMIT)
+        .clamp(1, MAX_QUERY_LIMIT);
+    let topics = state.telemetry_topics(&device_id).await?;
+    // An empty topic list would match every topic
+    let messages = if topics.is_empty() {
+        Vec::new()
+    } else {
+        state
+            .store
+            .device_messages(MessageQuery {
+                device_id,
+                topics,
+                from: query.from,
+                to: query.to,
+                limit,
+            })
+            .await
+            .map_err(store_error)?
+    };
+    if senml {
+        return Ok(senml_response(&messages));
+    }
+    Ok(Json(messages).into_response())
+}
+
+pub async fn invalid_messages(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<InvalidParams>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_QUERY_LIMIT)
+        .clamp(1, MAX_QUERY_LIMIT);
+    let count = state.store.count_invalid().await.map_err(store_error)?;
+    let messages = state.store.invalid(limit).await.map_err(store_error)?;
+    Ok(Json(serde_json::json!({
+        "count": count,
+        "messages": messages,
+    })))
+}
+
+pub async fn device_latest(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Query(params): Query<LatestParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let senml = wants_senml(params.format.as_deref())?;
+    let topics = state.telemetry_topics(&device_id).await?;
+    let latest = if topics.is_empty() {
+        None
+    } else {
+        state
+            .store
+            .latest(&device_id, topics)
+            .await
+            .map_err(store_error)?
+    }
+    .ok_or((StatusCode::NOT_FOUND, "no telemetry for device".into()))?;
+    if senml {
+        return Ok(senml_response(&[latest]));
+    }
+    Ok(Json(latest).into_response())
+}
+
+pub async fn device_presence(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+) -> Result<Json<Presence>, (StatusCode, String)> {
+    state
+        .presence
+        .get(&device_id)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "device never seen".into()))
+}
+
+pub async fn list_alerts(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AlertParams>,
+) -> Result<Json<Value>, (StatusCode, String)> {
+    let status = match params.status.as_deref() {
+        None => None,
+        Some("active") => Some(AlertStatus::Active),
+        Some("resolved") => Some(AlertStatus::Resolved),
+        Some(other) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("invalid status '{other}' (expected active or resolved)"),
+            ));
+        }
+    };
+    let (active, resolved) = state.alerts.alerts();
+    let keep = |alerts: Vec<Alert>, wanted: AlertStatus| -> Vec<Alert> {
+        if status.is_some_and(|s| s != wanted) {
+            return Vec::new();
+        }
+        alerts
+            .into_iter()
+            .filter(|a| params.device_id.as_ref().is_none_or(|d| *d == a.device_id))
+            .collect()
+    };
+    Ok(Json(serde_json::json!({
+        "active": keep(active, AlertStatus::Active),
+        "resolved": keep(resolved, AlertStatus::Resolved),
+    })))
+}
+
+/// Parse `500ms`, `30s`, `2m` or a bare number of seconds.
+pub fn parse_wait(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = value
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or((value, "s"), |idx| value.split_at(idx));
+    let number: u64 = number.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        _ => None,
+    }
+}
+
+pub async fn received(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ReceivedParams>,
+) -> Result<Json<Vec<StoredMessage>>, (StatusCode, String)> {
+    let wait = match params.wait.as_deref() {
+        Some(raw) => Some(
+            parse_wait(raw)
+                .ok_or((StatusCode::BAD_REQUEST, format!("invalid wait '{raw}'")))?
+                .min(MAX_RECEIVED_WAIT),
+        ),
+        None => None,
+    };
+    let query = ReceivedQuery {
+        topic: params.topic,
+        // A waiting test wants a new message, not one from an earlier run
+        since: params.since.or_else(|| wait.map(|_| Utc::now())),
+        contains: params.contains,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT as usize)
+            .clamp(1, MAX_QUERY_LIMIT as usize),
+    };
+
+    // Subscribe before querying so nothing slips between the two.
+    let mut rx = state.events.subscribe();
+    let found = state
+        .store
+        .received(query.clone())
+        .await
+        .map_err(store_error)?;
+    let Some(wait) = wait.filter(|_| found.is_empty()) else {
+        return Ok(Json(found));
+    };
+
+    let waited = tokio::time::timeout(wait, async {
+        loop {
+            match rx.recv().await {
+                Ok(message) if query.matches(&message) => return Ok(vec![message]),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let found = state.store.received(query.clone()).await?;
+                    if !found.is_empty() {
+                        return Ok(found);
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(Vec::new()),
+            }
+        }
+    })
+    .await;
+
+    match waited {
+        Ok(Ok(found)) if !found.is_empty() => Ok(Json(found)),
+        Ok(Err(e)) => Err(store_error(e)),
+        _ => Err((
+            StatusCode::REQUEST_TIMEOUT,
+            "no matching message received".into(),
+        )),
+    }
+}
@@ -1,4 +1,20 @@
+mod alerts;
+mod auth;
+mod codec;
+mod commands;
+mod dedup;
 mod handlers;
+mod metrics;
+mod outbox;
+mod overload;
+mod presence;
+mod publisher;
+mod schema;
+mod senml;
+mod shadow;
+mod store;
+mod stream;
+mod topic;
 mod types;
 
 use anyhow::{Context, Result};
@@ -6,17 +22,32 @@ use axum::{
     Router,
     routing::{get, post},
 };
-use rumqttc::{
-    AsyncClient, Event, Incoming, MqttOptions, Outgoing, QoS, TlsConfiguration, Transport,
-};
+use mqtt_client::{Backoff, Client, ConnectionState, Endpoint, Event, Options, Protocol, Scheme};
 use std::{net::SocketAddr, sync::Arc};
-use tokio::{fs, net::TcpListener};
+use tokio::net::TcpListener;
+use topic_layout::TopicLayout;
 use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use url::Url;
 
-use crate::handlers::{AppState, health, telemetry};
+use crate::alerts::{AlertEngine, evaluate_message};
+use crate::auth::AuthContext;
+use crate::codec::PayloadFormat;
+use crate::commands::{CommandTracker, get_command, list_commands, send_command};
+use crate::dedup::{Deduplicator, consumed_key};
+use crate::handlers::{
+    AppState, device_latest, device_presence, device_telemetry, health, invalid_messages,
+    list_alerts, list_devices, received, telemetry, telemetry_batch,
+};
+use crate::metrics::DeviceGauges;
+use crate::outbox::Outbox;
+use crate::overload::Limiter;
+use crate::presence::PresenceTracker;
+use crate::publisher::Publisher;
+use crate::schema::SchemaRegistry;
+use crate::shadow::{ShadowStore, get_shadow, handle_reported, update_shadow};
+use crate::store::{Retention, Store, device_id_from_topic};
+use crate::topic::matches_filter;
 
 fn read_env(key: &str, default: &str) -> String {
     match std::env::var(key) {
@@ -32,11 +63,12 @@ fn read_env_optional(key: &str) -> Option<String> {
         .filter(|v| !v.is_empty())
 }
 
-fn ensure_trailing_slash(mut value: String) -> String {
-    if !value.ends_with('/') {
-        value.push('/');
+fn read_env_secs(key: &str, default: u64) -> Option<std::time::Duration> {
+    match read_env(key, &default.to_string()).parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(std::time::Duration::from_secs(secs)),
+        Err(_) => Some(std::time::Duration::from_secs(default)),
     }
-    value
 }
 
 #[tokio::main]
@@ -49,122 +81,422 @@ async fn main() -> Result<()> {
         .with(tracing_subscriber::EnvFilter::new(filter))
         .with(tracing_subscriber::fmt::layer())
         .init();
+    metrics::init();
 
     // MQTT config
     let username = read_env("MQTT_USERNAME", "devuser");
     let password = read_env("MQTT_PASSWORD", "devpass");
     // Prefer MQTT_TELEMETRY_TOPIC for a concrete publish path in CI; fallback to subscription pattern
-    let topics_csv = match std::env::var("MQTT_TELEMETRY_TOPIC") {
-        Ok(v) if !v.trim().is_empty() => v.trim().to_string(),
-        _ => read_env("MQTT_TOPICS", "argus/devices/+"),
+    let topics_csv =
+        read_env_optional("MQTT_TELEMETRY_TOPIC").or_else(|| read_env_optional("MQTT_TOPICS"));
+    let default_endpoint = Endpoint::new(
+        Scheme::Tls,
+        &read_env("MQTT_HOST", "mqtt"),
+        read_env("MQTT_PORT", "8883").parse().unwrap_or(8883),
+    );
+    let endpoint = match read_env_optional("MQTT_URL").map(|url| Endpoint::parse(&url)) {
+        Some(Ok(endpoint)) => endpoint,
+        Some(Err(e)) => {
+            tracing::warn!("MQTT_URL parse error: {e}; falling back to {default_endpoint}");
+            default_endpoint
+        }
+        None => default_endpoint,
     };
-    let default_host = read_env("MQTT_HOST", "mqtt");
-    let default_port: u16 = read_env("MQTT_PORT", "8883").parse().unwrap_or(8883);
-    let (host, port) = match std::env::var("MQTT_URL") {
-        Ok(url) if !url.trim().is_empty() => match Url::parse(url.trim()) {
-            Ok(u) => {
-                let host = u.host_str().unwrap_or(&default_host).to_string();
-                let port = u.port().unwrap_or(default_port);
-                (host, port)
-            }
-            Err(e) => {
-                tracing::warn!(
-                    "MQTT_URL parse error: {e}; falling back to {}:{}",
-                    default_host,
-                    default_port
-                );
-                (default_host, default_port)
-            }
+    if endpoint.is_plain_on_tls_port() {
+        tracing::warn!(
+            "MQTT_URL {endpoint} uses plain TCP on the TLS port; use mqtts:// for a TLS listener"
+        );
+    }
+    let protocol = Protocol::parse(&read_env("MQTT_PROTOCOL", "3.1.1"))
+        .context("MQTT_PROTOCOL must be 3.1.1 or 5")?;
+    tracing::info!("mqtt -> {endpoint} as mock-sink (MQTT {protocol})");
+
+    // Certificates are only needed for mqtts:// and wss:// brokers.
+    let tls = endpoint.tls_from_env()?;
+    let channel_capacity: usize = read_env("MOCK_SINK_MQTT_CHANNEL_CAPACITY", "32")
+        .parse()
+        .unwrap_or(32);
+    let (client, mut eventloop) = Client::new(
+        protocol,
+        Options {
+            client_id: "mock-sink".into(),
+            endpoint,
+            tls,
+            credentials: Some((username, password)),
+            keep_alive: std::time::Duration::from_secs(30),
+        },
+        channel_capacity.max(1),
+    );
+    let publisher = Publisher::new(client.clone());
+
+    let topics = TopicLayout::from_env().context("invalid MQTT topic layout")?;
+    tracing::info!("mqtt topic prefix -> {}", topics.prefix());
+    tracing::info!("telemetry topic -> {}", topics.telemetry().as_str());
+
+    // Persist consumed messages so test runs leave an inspectable record
+    let db_path = read_env("MOCK_SINK_DB_PATH", "/data/mock-sink.db");
+    let retention = Retention {
+        max_age: read_env_secs("MOCK_SINK_RETENTION_MAX_AGE_SECS", 7 * 24 * 3600),
+        max_messages: match read_env("MOCK_SINK_RETENTION_MAX_MESSAGES", "100000").parse() {
+            Ok(0) => None,
+            Ok(n) => Some(n),
+            Err(_) => Some(100_000),
         },
-        _ => (default_host, default_port),
     };
-    tracing::info!("mqtt -> {host}:{port} as mock-sink");
-
-    let mut opts = MqttOptions::new("mock-sink", host, port);
-    opts.set_credentials(username, password);
-    opts.set_keep_alive(std::time::Duration::from_secs(30));
-
-    let ca_path = read_env("MQTT_CA_PATH", "/certs/ca.crt");
-    let ca = fs::read(&ca_path)
-        .await
-        .with_context(|| format!("failed to read MQTT_CA_PATH at {ca_path}"))?;
-    let client_auth = match (
-        read_env_optional("MQTT_CERT_PATH"),
-        read_env_optional("MQTT_KEY_PATH"),
-    ) {
-        (Some(cert_path), Some(key_path)) => {
-            let cert = fs::read(&cert_path)
-                .await
-                .with_context(|| format!("failed to read MQTT_CERT_PATH at {cert_path}"))?;
-            let key = fs::read(&key_path)
-                .await
-                .with_context(|| format!("failed to read MQTT_KEY_PATH at {key_path}"))?;
-            Some((cert, key))
+    let store = if db_path == ":memory:" {
+        Store::open_in_memory(retention)?
+    } else {
+        Store::open(std::path::Path::new(&db_path), retention)?
+    };
+    tracing::info!("telemetry store -> {db_path} ({retention:?})");
+
+    // Accepted readings wait here while the broker is unreachable
+    let connection = ConnectionState::default();
+    let outbox_path = read_env("MOCK_SINK_OUTBOX_PATH", &db_path);
+    let outbox = Arc::new(if outbox_path == ":memory:" {
+        Outbox::open_in_memory(connection.clone())?
+    } else {
+        Outbox::open(std::path::Path::new(&outbox_path), connection.clone())?
+    });
+    tracing::info!("outbox -> {outbox_path} ({} queued)", outbox.depth());
+    let publish_confirm_timeout = read_env_secs("MOCK_SINK_PUBLISH_CONFIRM_TIMEOUT_SECS", 5)
+        .unwrap_or(std::time::Duration::from_secs(5));
+    outbox.spawn_drain(publisher.clone(), publish_confirm_timeout);
+
+    let prune_store = store.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            match prune_store.prune(chrono::Utc::now()).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("store retention removed {n} messages"),
+                Err(e) => tracing::error!("store retention failed: {e}"),
+            }
         }
-        (None, None) => None,
-        _ => {
-            tracing::warn!(
-                "MQTT client certificate/key not fully specified; proceeding without client auth"
-            );
-            None
+    });
+
+    let schemas = Arc::new(match read_env_optional("MOCK_SINK_SCHEMA_DIR") {
+        Some(dir) => {
+            let registry = SchemaRegistry::load_dir(std::path::Path::new(&dir))?;
+            tracing::info!("loaded {} telemetry schema(s) from {dir}", registry.len());
+            registry
         }
-    };
-    let transport = Transport::tls_with_config(TlsConfiguration::Simple {
-        ca,
-        alpn: None,
-        client_auth,
+        None => SchemaRegistry::default(),
+    });
+
+    let alerts = Arc::new(match read_env_optional("MOCK_SINK_ALERT_RULES") {
+        Some(path) => {
+            let engine = AlertEngine::load(std::path::Path::new(&path))?;
+            tracing::info!("loaded {} alert rule(s) from {path}", engine.len());
+            engine
+        }
+        None => AlertEngine::default(),
     });
-    opts.set_transport(transport);
-    let (client, mut eventloop) = AsyncClient::new(opts, 32);
+
+    let presence_timeout = read_env_secs("MOCK_SINK_PRESENCE_TIMEOUT_SECS", 120);
+    tracing::info!("presence offline timeout -> {presence_timeout:?}");
+    let presence = Arc::new(PresenceTracker::new(topics.clone(), presence_timeout));
+    if let Some(timeout) = presence_timeout {
+        let sweep_presence = Arc::clone(&presence);
+        let sweep_publisher = publisher.clone();
+        let sweep_topics = topics.clone();
+        tokio::spawn(async move {
+            let period = (timeout / 4).clamp(
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(15),
+            );
+            let mut ticker = tokio::time::interval(period);
+            loop {
+                ticker.tick().await;
+                for event in sweep_presence.sweep(chrono::Utc::now()) {
+                    tracing::info!("{} offline (no activity for {timeout:?})", event.device_id);
+                    match sweep_topics.presence_topic(&event.device_id) {
+                        Ok(topic) => sweep_publisher.publish_event(topic, &event),
+                        Err(e) => tracing::warn!("no presence topic for {}: {e}", event.device_id),
+                    }
+                }
+            }
+        });
+    }
+
+    let command_retention = read_env_secs("MOCK_SINK_COMMAND_RETENTION_SECS", 3600);
+    tracing::info!("finished commands kept for -> {command_retention:?}");
+    let commands = Arc::new(CommandTracker::new(
+        read_env_secs("MOCK_SINK_COMMAND_TIMEOUT_SECS", 60)
+            .unwrap_or(std::time::Duration::from_secs(60)),
+        command_retention,
+    ));
+
+    let shadows = Arc::new(ShadowStore::default());
+
+    let device_metrics_stale = read_env_secs("MOCK_SINK_DEVICE_METRICS_STALE_SECS", 300);
+    tracing::info!("device metrics stale after -> {device_metrics_stale:?}");
+    let device_gauges = Arc::new(DeviceGauges::new(topics.clone(), device_metrics_stale));
+
+    let dedup_window = read_env_secs("MOCK_SINK_DEDUP_WINDOW_SECS", 300);
+    tracing::info!("dedup window -> {dedup_window:?}");
+    // Consumed messages include the sink's own forwards, so they get their
+    // own window rather than sharing the HTTP one
+    let consumed_dedup = Deduplicator::new(dedup_window);
+
+    let (events, _) = tokio::sync::broadcast::channel(1024);
+
+    // Subscribe to topics so Compose smoke test can assert consumption, plus
+    // the device replies the sink acts on unless a configured filter covers them.
+    // A clean-session broker forgets them, so they are re-issued on every ConnAck
+    let mut subscriptions: Vec<String> = topics_csv
+        .unwrap_or_else(|| topics.telemetry().filter())
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect();
+    for template in [
+        topics.status(),
+        topics.command_responses(),
+        topics.shadow_reported(),
+    ] {
+        let sample = topics.device_topic(template, "device")?;
+        if !subscriptions.iter().any(|t| matches_filter(t, &sample)) {
+            subscriptions.push(template.filter());
+        }
+    }
+    let subscriptions: Arc<[String]> = subscriptions.into();
+
+    let reconnect_max = read_env("MQTT_RECONNECT_MAX_SECS", "30");
 
     // Drive MQTT eventloop in background
+    let loop_store = store.clone();
+    let loop_events = events.clone();
+    let loop_schemas = Arc::clone(&schemas);
+    let loop_topics = topics.clone();
+    let loop_alerts = Arc::clone(&alerts);
+    let loop_presence = Arc::clone(&presence);
+    let loop_commands = Arc::clone(&commands);
+    let loop_shadows = Arc::clone(&shadows);
+    let loop_device_gauges = Arc::clone(&device_gauges);
+    let loop_publisher = publisher.clone();
+    let loop_client = client.clone();
+    let loop_connection = connection.clone();
     tokio::spawn(async move {
+        let mut backoff = Backoff::from_max_secs(&reconnect_max);
         loop {
             match eventloop.poll().await {
-                Ok(Event::Incoming(inc)) => match inc {
-                    Incoming::ConnAck(ack) => tracing::info!("mqtt connected: {ack:?}"),
-                    Incoming::Publish(p) => {
+                Ok(event) => match event {
+                    Event::ConnAck { session_present } => {
+                        tracing::info!("mqtt connected (session present: {session_present})");
+                        loop_connection.on_connack();
+                        backoff.reset();
+                        // Subscribing waits on the request channel this loop drains
+                        let client = loop_client.clone();
+                        let subscriptions = Arc::clone(&subscriptions);
+                        tokio::spawn(async move {
+                            for t in subscriptions.iter() {
+                                match client.subscribe(t).await {
+                                    Ok(_) => tracing::info!("subscribed: {t}"),
+                                    Err(e) => tracing::error!("subscribe error for '{t}': {e}"),
+                                }
+                            }
+                        });
+                    }
+                    Event::Publish(p) => {
+                        metrics::MESSAGES_CONSUMED
+                            .with_label_values(&[p.topic.as_str()])
+                            .inc();
                         let payload = String::from_utf8_lossy(&p.payload);
                         tracing::info!("{} <- {}", p.topic, payload);
+                        let device_id = device_id_from_topic(&loop_topics, &p.topic);
+                        let format =
+                            PayloadFormat::resolve(&p.topic, p.properties.content_type.as_deref());
+                        if let Some(device_id) = device_id
+                            && let Some(key) =
+                                consumed_key(&loop_topics, device_id, &p.topic, format, &p.payload)
+                            && !consumed_dedup.claim(&key, std::time::Instant::now())
+                        {
+                            metrics::DUPLICATES.with_label_values(&["mqtt"]).inc();
+                            tracing::info!("{} duplicate reading {key} skipped", p.topic);
+                            continue;
+                        }
+                        let violations =
+                            loop_schemas.validate_payload(&p.topic, format, &p.payload);
+                        if !violations.is_empty() {
+                            tracing::warn!(
+                                "{} failed schema validation: {} violation(s)",
+                                p.topic,
+                                violations.len()
+                            );
+                        }
+                        match loop_store
+                            .insert(&p, device_id, format, chrono::Utc::now(), violations)
+                            .await
+                        {
+                            Ok(message) => {
+                                if let Some(device_id) = &message.device_id
+                                    && loop_topics.command_responses().device_id(&message.topic)
+                                        == Some(device_id)
+                                    && let Some(command) = loop_commands.handle_reply(
+                                        device_id,
+                                        &message.payload,
+                                        p.properties.correlation_data.as_deref(),
+                                    )
+                                {
+                                    tracing::info!(
+                                        "command {} for {device_id} -> {:?}",
+                                        command.correlation_id,
+                                        command.status
+                                    );
+                                }
+                                if let Some(device_id) = &message.device_id
+                                    && loop_topics.shadow_reported().device_id(&message.topic)
+                                        == Some(device_id)
+                                {
+                                    handle_reported(
+                                        &loop_shadows,
+                                        &loop_publisher,
+                                        &loop_topics,
+                                        device_id,
+                                        &message.payload,
+                                    );
+                                }
+                                if let Some(event) = loop_presence.observe(&message) {
+                                    tracing::info!(
+                                        "{} {:?} ({:?})",
+                                        event.device_id,
+                                        event.state,
+                                        event.source
+                                    );
+                                    match loop_topics.presence_topic(&event.device_id) {
+                                        Ok(topic) => loop_publisher.publish_event(topic, &event),
+                                        Err(e) => tracing::warn!(
+                                            "no presence topic for {}: {e}",
+                                            event.device_id
+                                        ),
+                                    }
+                                }
+                                loop_device_gauges.observe(&message);
+                                evaluate_message(
+                                    &loop_alerts,
+                                    &loop_publisher,
+                                    &loop_topics,
+                                    &message,
+                                );
+                                // No receivers is fine; nobody is waiting.
+                                let _ = loop_events.send(message);
+                            }
+                            Err(e) => tracing::error!("store insert failed for '{}': {e}", p.topic),
+                        }
                     }
-                    Incoming::PubAck(ack) => tracing::info!("mqtt puback <- pkid={}", ack.pkid),
-                    other => tracing::trace!("mqtt incoming: {other:?}"),
-                },
-                Ok(Event::Outgoing(out)) => match out {
-                    Outgoing::Publish(pkid) => tracing::debug!("mqtt publish -> pkid={}", pkid),
-                    other => tracing::trace!("mqtt outgoing: {other:?}"),
+                    Event::PubAck(pkid) => {
+                        tracing::info!("mqtt puback <- pkid={}", pkid);
+                        loop_publisher.on_puback(pkid);
+                    }
+                    Event::SubAck { pkid, refused } => {
+                        if !refused.is_empty() {
+                            tracing::error!("mqtt subscribe pkid={pkid} refused: {refused:?}");
+                        }
+                    }
+                    Event::OutgoingPublish(pkid) => {
+                        tracing::debug!("mqtt publish -> pkid={}", pkid);
+                        loop_publisher.on_outgoing_publish(pkid);
+                    }
+                    Event::AwaitAck(pkid) => {
+                        tracing::debug!("mqtt publish waits for pkid={} to be acked", pkid);
+                        loop_publisher.on_await_ack(pkid);
+                    }
+                    Event::Other(other) => tracing::trace!("mqtt {other}"),
                 },
                 Err(e) => {
-                    tracing::error!("mqtt eventloop error: {e}; retrying in 2s");
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                    if let Some(reason) = e.rejected_publish() {
+                        loop_publisher.on_rejected(&reason);
+                    }
+                    metrics::MQTT_RECONNECTS.inc();
+                    loop_connection.on_error(&e);
+                    let delay = backoff.next_delay();
+                    tracing::error!("mqtt eventloop error: {e}; retrying in {delay:?}");
+                    tokio::time::sleep(delay).await;
                 }
             }
         }
     });
 
-    // Subscribe to topics so Compose smoke test can assert consumption
-    for t in topics_csv.split(',') {
-        let t = t.trim();
-        if t.is_empty() {
-            continue;
-        }
-        match client.subscribe(t, QoS::AtLeastOnce).await {
-            Ok(_) => tracing::info!("subscribed: {t}"),
-            Err(e) => tracing::error!("subscribe error for '{t}': {e}"),
-        }
-    }
-
     // HTTP server with Axum
-    let topic_prefix = ensure_trailing_slash(read_env("MQTT_TOPIC_PREFIX", "argus/devices/"));
-    tracing::info!("mqtt topic prefix -> {topic_prefix}");
+    let allow_anonymous = read_env("MOCK_SINK_ALLOW_ANONYMOUS", "false") == "true";
+    let auth = if allow_anonymous {
+        tracing::warn!(
+            "MOCK_SINK_ALLOW_ANONYMOUS=true; POST /telemetry accepts unauthenticated requests"
+        );
+        None
+    } else {
+        let validate_url = read_env(
+            "MOCK_AUTH_VALIDATE_URL",
+            "http://mock-auth:8080/auth/token/validate",
+        );
+        tracing::info!("device tokens validated via {validate_url}");
+        Some(AuthContext {
+            client: reqwest::Client::builder().build()?,
+            validate_url,
+        })
+    };
+    let max_inflight: usize = read_env("MOCK_SINK_MAX_INFLIGHT", "128")
+        .parse()
+        .unwrap_or(128);
+    let overload_wait = read_env_secs("MOCK_SINK_OVERLOAD_WAIT_SECS", 0);
+    tracing::info!(
+        "telemetry in-flight limit -> {max_inflight}; over the limit {}",
+        match overload_wait {
+            Some(wait) => format!("wait up to {wait:?}"),
+            None => "reject with 503".into(),
+        }
+    );
     let state = Arc::new(AppState {
-        mqtt: client.clone(),
-        topic_prefix,
+        mqtt: publisher,
+        topics,
+        auth,
+        store,
+        events,
+        schemas,
+        batch_max_items: read_env("MOCK_SINK_BATCH_MAX_ITEMS", "1000")
+            .parse()
+            .unwrap_or(1000),
+        alerts,
+        presence,
+        commands,
+        shadows,
+        device_gauges,
+        outbox,
+        publish_confirm: read_env("MOCK_SINK_PUBLISH_CONFIRM", "false") == "true",
+        publish_confirm_timeout,
+        limiter: Arc::new(Limiter::new(max_inflight.max(1), overload_wait)),
+        dedup: Arc::new(Deduplicator::new(dedup_window)),
+        connection,
     });
     let app = Router::new()
         .route("/health", get(health))
+        .route("/metrics", get(metrics::render))
+        .route("/metrics/devices", get(metrics::device_metrics))
         .route("/telemetry", post(telemetry))
+        .route("/telemetry/batch", post(telemetry_batch))
+        .route("/received", get(received))
+        .route("/stream", get(stream::sse))
+        .route("/ws", get(stream::ws))
+        .route("/invalid", get(invalid_messages))
+        .route("/alerts", get(list_alerts))
+        .route("/devices", get(list_devices))
+        .route("/devices/:device_id/telemetry", get(device_telemetry))
+        .route("/devices/:device_id/latest", get(device_latest))
+        .route("/devices/:device_id/presence", get(device_presence))
+        .route(
+            "/devices/:device_id/commands",
+            post(send_command).get(list_commands),
+        )
+        .route("/devices/:device_id/commands/:id", get(get_command))
+        .route(
+            "/devices/:device_id/shadow",
+            get(get_shadow).patch(update_shadow),
+        )
         .with_state(state)
+        .layer(axum::middleware::from_fn(metrics::track))
         .layer(
             TraceLayer::new_for_http().make_span_with(|req: &axum::http::Request<_>| {
                 let request_id = req
@@ -193,3 +525,7 @@ async fn main() -> Result<()> {
     axum::serve(listener, app).await?;
     Ok(())
 }
+
+#[cfg(test)]
+#[path = "../tests/mod.rs"]
+mod tests;
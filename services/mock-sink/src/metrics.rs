@@ -0,0 +1,202 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use prometheus::{
+    GaugeVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, register_int_counter,
+    register_int_counter_vec, register_int_gauge,
+};
+use service_metrics::{RESULTS, encode, result_label};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+use topic_layout::TopicLayout;
+
+use crate::handlers::AppState;
+use crate::store::{StoredMessage, is_telemetry};
+
+pub use service_metrics::{render, track};
+
+pub static MQTT_PUBLISHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mqtt_publish_total",
+        "MQTT publishes handed to the client, by result (ok/error)",
+        &["result"]
+    )
+    .expect("register mqtt_publish_total")
+});
+
+pub static MQTT_RECONNECTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "mqtt_reconnects_total",
+        "MQTT event loop errors followed by a reconnect attempt"
+    )
+    .expect("register mqtt_reconnects_total")
+});
+
+pub static MESSAGES_CONSUMED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mock_sink_messages_consumed_total",
+        "MQTT messages consumed by topic",
+        &["topic"]
+    )
+    .expect("register mock_sink_messages_consumed_total")
+});
+
+pub static OUTBOX_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "mock_sink_outbox_depth",
+        "Accepted readings queued until the broker is reachable"
+    )
+    .expect("register mock_sink_outbox_depth")
+});
+
+pub static HTTP_INFLIGHT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "mock_sink_telemetry_inflight",
+        "Telemetry requests currently being forwarded"
+    )
+    .expect("register mock_sink_telemetry_inflight")
+});
+
+pub static OVERLOAD_REJECTIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "mock_sink_overload_rejections_total",
+        "Telemetry requests rejected with 503 because the in-flight limit was reached"
+    )
+    .expect("register mock_sink_overload_rejections_total")
+});
+
+pub static DUPLICATES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "mock_sink_duplicates_total",
+        "Duplicate readings dropped within the dedup window, by source (http/mqtt)",
+        &["source"]
+    )
+    .expect("register mock_sink_duplicates_total")
+});
+
+/// Register every collector up front so dashboards see zeroes, not gaps.
+pub fn init() {
+    service_metrics::init();
+    LazyLock::force(&MQTT_RECONNECTS);
+    LazyLock::force(&MESSAGES_CONSUMED);
+    LazyLock::force(&OUTBOX_DEPTH);
+    LazyLock::force(&HTTP_INFLIGHT);
+    LazyLock::force(&OVERLOAD_REJECTIONS);
+    for source in ["http", "mqtt"] {
+        DUPLICATES.with_label_values(&[source]);
+    }
+    for result in RESULTS {
+        MQTT_PUBLISHES.with_label_values(&[result]);
+    }
+}
+
+pub fn record_publish<T, E>(result: &Result<T, E>) {
+    MQTT_PUBLISHES
+        .with_label_values(&[result_label(result)])
+        .inc();
+}
+
+struct DeviceReadings {
+    last_seen: DateTime<Utc>,
+    values: BTreeMap<String, f64>,
+}
+
+/// Latest numeric telemetry per device, exported at `/metrics/devices`
+/// separately from the service metrics.
+pub struct DeviceGauges {
+    topics: TopicLayout,
+    /// Devices silent for longer than this are dropped, so their series go
+    /// stale in Prometheus; `None` keeps them forever.
+    stale_after: Option<Duration>,
+    devices: Mutex<HashMap<String, DeviceReadings>>,
+}
+
+impl DeviceGauges {
+    pub fn new(topics: TopicLayout, stale_after: Option<Duration>) -> Self {
+        Self {
+            topics,
+            stale_after,
+            devices: Mutex::default(),
+        }
+    }
+
+    /// Record the numeric fields of a consumed telemetry message; flags count
+    /// as `1`/`0` and strings are skipped.
+    pub fn observe(&self, message: &StoredMessage) {
+        let Some(device_id) = &message.device_id else {
+            return;
+        };
+        if !is_telemetry(&self.topics, &message.topic, device_id) {
+            return;
+        }
+        let mut devices = self.devices.lock().expect("device gauges poisoned");
+        for reading in message.readings() {
+            let entry =
+                devices
+                    .entry(reading.device_id.clone())
+                    .or_insert_with(|| DeviceReadings {
+                        last_seen: message.received_at,
+                        values: BTreeMap::new(),
+                    });
+            entry.last_seen = entry.last_seen.max(message.received_at);
+            for (name, value) in reading.resolved_metrics() {
+                if let Some(value) = value.as_f64() {
+                    entry.values.insert(name, value);
+                }
+            }
+        }
+    }
+
+    /// Drop stale devices and gather the remaining readings.
+    pub fn gather(
+        &self,
+        now: DateTime<Utc>,
+    ) -> prometheus::Result<Vec<prometheus::proto::MetricFamily>> {
+        let telemetry = GaugeVec::new(
+            Opts::new(
+                "argus_telemetry",
+                "Latest value of each numeric telemetry field",
+            ),
+            &["device_id", "metric"],
+        )?;
+        let last_seen = GaugeVec::new(
+            Opts::new(
+                "argus_telemetry_last_seen_timestamp_seconds",
+                "Unix time of the latest telemetry from each device",
+            ),
+            &["device_id"],
+        )?;
+        let registry = Registry::new();
+        registry.register(Box::new(telemetry.clone()))?;
+        registry.register(Box::new(last_seen.clone()))?;
+
+        let mut devices = self.devices.lock().expect("device gauges poisoned");
+        if let Some(stale_after) = self.stale_after {
+            devices.retain(|_, d| (now - d.last_seen).to_std().unwrap_or_default() <= stale_after);
+        }
+        for (device_id, readings) in devices.iter() {
+            last_seen
+                .with_label_values(&[device_id])
+                .set(readings.last_seen.timestamp_millis() as f64 / 1000.0);
+            for (metric, value) in &readings.values {
+                telemetry
+                    .with_label_values(&[device_id, metric])
+                    .set(*value);
+            }
+        }
+        Ok(registry.gather())
+    }
+}
+
+pub async fn device_metrics(State(state): State<Arc<AppState>>) -> Response {
+    match state.device_gauges.gather(Utc::now()) {
+        Ok(families) => encode(&families),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use mqtt_client::ConnectionState;
+use rusqlite::{Connection, params};
+use std::{
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+use crate::metrics;
+use crate::publisher::{Publisher, telemetry_properties};
+
+/// Rows published per drain pass before re-checking the connection.
+const DRAIN_BATCH: u32 = 100;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS outbox (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    topic       TEXT NOT NULL,
+    payload     BLOB NOT NULL,
+    enqueued_at INTEGER NOT NULL
+);
+";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Queued {
+    pub id: i64,
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+/// Durable FIFO of accepted readings waiting for the broker. It reads the
+/// shared MQTT connection state to decide when to drain.
+pub struct Outbox {
+    conn: Arc<Mutex<Connection>>,
+    depth: AtomicU64,
+    connection: ConnectionState,
+    wake: Notify,
+}
+
+impl Outbox {
+    pub fn open(path: &Path, connection: ConnectionState) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create outbox directory {}", parent.display())
+            })?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open outbox at {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn, connection)
+    }
+
+    pub fn open_in_memory(connection: ConnectionState) -> Result<Self> {
+        Self::init(Connection::open_in_memory()?, connection)
+    }
+
+    fn init(conn: Connection, connection: ConnectionState) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("failed to initialise outbox schema")?;
+        let depth: u64 = conn.query_row("SELECT COUNT(*) FROM outbox", [], |row| row.get(0))?;
+        metrics::OUTBOX_DEPTH.set(depth as i64);
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            depth: AtomicU64::new(depth),
+            connection,
+            wake: Notify::new(),
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("outbox mutex poisoned");
+            f(&mut conn)
+        })
+        .await?
+        .map_err(Into::into)
+    }
+
+    pub fn depth(&self) -> u64 {
+        self.depth.load(Ordering::SeqCst)
+    }
+
+    /// Append readings in order; they are persisted before this returns.
+    pub async fn enqueue(&self, items: Vec<(String, Vec<u8>)>) -> Result<()> {
+        let count = items.len() as u64;
+        let now = Utc::now().timestamp_millis();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO outbox (topic, payload, enqueued_at) VALUES (?1, ?2, ?3)",
+                )?;
+                for (topic, payload) in items {
+                    insert.execute(params![topic, payload, now])?;
+                }
+            }
+            tx.commit()
+        })
+        .await?;
+        let depth = self.depth.fetch_add(count, Ordering::SeqCst) + count;
+        metrics::OUTBOX_DEPTH.set(depth as i64);
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Oldest queued readings first.
+    pub async fn peek(&self, limit: u32) -> Result<Vec<Queued>> {
+        self.with_conn(move |conn| {
+            let mut stmt =
+                conn.prepare("SELECT id, topic, payload FROM outbox ORDER BY id LIMIT ?1")?;
+            stmt.query_map(params![limit], |row| {
+                Ok(Queued {
+                    id: row.get(0)?,
+                    topic: row.get(1)?,
+                    payload: row.get(2)?,
+                })
+            })?
+            .collect()
+        })
+        .await
+    }
+
+    pub async fn remove(&self, id: i64) -> Result<()> {
+        let removed = self
+            .with_conn(move |conn| conn.execute("DELETE FROM outbox WHERE id = ?1", params![id]))
+            .await?;
+        let depth = self.depth.fetch_sub(removed as u64, Ordering::SeqCst) - removed as u64;
+        metrics::OUTBOX_DEPTH.set(depth as i64);
+        Ok(())
+    }
+
+    /// Publish queued readings in order while connected, removing each one
+    /// only once the broker acknowledges it; returns how many were
+    /// acknowledged. A reading without a PubAck within `ack_timeout` stays
+    /// queued and is sent again on the next pass.
+    pub async fn drain(&self, mqtt: &Publisher, ack_timeout: Duration) -> Result<usize> {
+        let mut acked = 0;
+        loop {
+            let batch = self.peek(DRAIN_BATCH).await?;
+            let mut pending = Vec::with_capacity(batch.len());
+            for item in batch {
+                if !self.connection.is_connected() {
+                    break;
+                }
+                let properties = telemetry_properties(&item.topic);
+                let ack = mqtt
+                    .publish_confirmed(item.topic.clone(), item.payload, properties)
+                    .await
+                    .with_context(|| format!("publish queued reading to {}", item.topic))?;
+                pending.push((item.id, item.topic, ack));
+            }
+            if pending.is_empty() {
+                return Ok(acked);
+            }
+            let deadline = tokio::time::Instant::now() + ack_timeout;
+            for (id, topic, ack) in pending {
+                match tokio::time::timeout_at(deadline, ack).await {
+                    Ok(Ok(Ok(()))) => {}
+                    Ok(Ok(Err(reason))) => {
+                        bail!("broker rejected queued reading to {topic}: {reason}")
+                    }
+                    _ => bail!(
+                        "broker did not acknowledge queued reading to {topic} within {}ms",
+                        ack_timeout.as_millis()
+                    ),
+                }
+                self.remove(id).await?;
+                acked += 1;
+            }
+        }
+    }
+
+    /// Drain the queue whenever the client is connected and readings arrive.
+    pub fn spawn_drain(self: &Arc<Self>, mqtt: Publisher, ack_timeout: Duration) {
+        let outbox = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                outbox.connection.connected().await;
+                match outbox.drain(&mqtt, ack_timeout).await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!("outbox drained {n} reading(s)"),
+                    Err(e) => {
+                        tracing::error!("outbox drain failed: {e:#}");
+                        tokio::time::sleep(RETRY_DELAY).await;
+                        continue;
+                    }
+                }
+                // Wait for new readings; the timeout covers a reconnect that
+                // raced with the last pass.
+                let _ = tokio::time::timeout(RETRY_DELAY, outbox.wake.notified()).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::metrics;
+
+/// How long clients are told to back off when a request is rejected.
+pub const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Bounds how many telemetry requests are forwarded at once. Requests over
+/// the limit either wait up to `wait` for a slot or are rejected at once.
+pub struct Limiter {
+    slots: Semaphore,
+    limit: usize,
+    wait: Option<Duration>,
+}
+
+/// A slot in the limiter, released on drop.
+pub struct Permit<'a> {
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        metrics::HTTP_INFLIGHT.dec();
+    }
+}
+
+impl Limiter {
+    pub fn new(limit: usize, wait: Option<Duration>) -> Self {
+        Self {
+            slots: Semaphore::new(limit),
+            limit,
+            wait,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.limit - self.slots.available_permits()
+    }
+
+    /// `None` when the service is overloaded.
+    pub async fn acquire(&self) -> Option<Permit<'_>> {
+        let permit = match self.wait {
+            Some(wait) => tokio::time::timeout(wait, self.slots.acquire())
+                .await
+                .ok()?
+                .ok()?,
+            None => self.slots.try_acquire().ok()?,
+        };
+        metrics::HTTP_INFLIGHT.inc();
+        Some(Permit { _permit: permit })
+    }
+}
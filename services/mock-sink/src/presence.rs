@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use topic_layout::TopicLayout;
+
+use crate::store::{StoredMessage, device_id_from_topic, is_telemetry};
+
+/// Connect/disconnect transitions kept per device.
+const HISTORY_LEN: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceState {
+    Online,
+    Offline,
+}
+
+/// What caused a presence change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceSource {
+    /// A message on `{device}/status`.
+    Status,
+    /// An offline status with `"reason": "lwt"`, i.e. the device's last will.
+    Lwt,
+    /// A reading on one of the device telemetry topics.
+    Telemetry,
+    /// Nothing heard from the device within the offline timeout.
+    Timeout,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PresenceEvent {
+    pub device_id: String,
+    pub state: PresenceState,
+    pub source: PresenceSource,
+    pub at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Response body for `GET /devices/{device_id}/presence`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Presence {
+    pub device_id: String,
+    pub state: PresenceState,
+    /// When the current state began.
+    pub since: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Transitions, oldest first.
+    pub history: Vec<PresenceEvent>,
+}
+
+struct DevicePresence {
+    state: PresenceState,
+    since: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    history: VecDeque<PresenceEvent>,
+}
+
+/// Online/offline state per device from status messages, last-will messages
+/// and telemetry activity.
+pub struct PresenceTracker {
+    topics: TopicLayout,
+    /// `None` disables the offline timeout.
+    timeout: Option<Duration>,
+    devices: Mutex<HashMap<String, DevicePresence>>,
+}
+
+impl PresenceTracker {
+    pub fn new(topics: TopicLayout, timeout: Option<Duration>) -> Self {
+        Self {
+            topics,
+            timeout,
+            devices: Mutex::default(),
+        }
+    }
+
+    /// Update presence from a consumed message; returns the transition, if any.
+    pub fn observe(&self, message: &StoredMessage) -> Option<PresenceEvent> {
+        let device_id = device_id_from_topic(&self.topics, &message.topic)?;
+        let (state, source, reason) =
+            if self.topics.status().device_id(&message.topic) == Some(device_id) {
+                let (state, reason) = parse_status(&message.payload)?;
+                let source = match (state, reason.as_deref()) {
+                    (PresenceState::Offline, Some("lwt")) => PresenceSource::Lwt,
+                    _ => PresenceSource::Status,
+                };
+                (state, source, reason)
+            } else if is_telemetry(&self.topics, &message.topic, device_id) {
+                (PresenceState::Online, PresenceSource::Telemetry, None)
+            } else {
+                return None;
+            };
+        self.record(device_id, state, source, reason, message.received_at)
+    }
+
+    fn record(
+        &self,
+        device_id: &str,
+        state: PresenceState,
+        source: PresenceSource,
+        reason: Option<String>,
+        at: DateTime<Utc>,
+    ) -> Option<PresenceEvent> {
+        let mut devices = self.devices.lock().expect("presence poisoned");
+        let entry = devices
+            .entry(device_id.to_string())
+            .or_insert_with(|| DevicePresence {
+                // Unknown devices count as offline until heard from
+                state: PresenceState::Offline,
+                since: at,
+                last_seen: at,
+                history: VecDeque::new(),
+            });
+        entry.last_seen = entry.last_seen.max(at);
+        if entry.state == state && !entry.history.is_empty() {
+            return None;
+        }
+        let event = PresenceEvent {
+            device_id: device_id.to_string(),
+            state,
+            source,
+            at,
+            reason,
+        };
+        entry.state = state;
+        entry.since = at;
+        if entry.history.len() == HISTORY_LEN {
+            entry.history.pop_front();
+        }
+        entry.history.push_back(event.clone());
+        Some(event)
+    }
+
+    /// Mark online devices silent for longer than the timeout as offline.
+    pub fn sweep(&self, now: DateTime<Utc>) -> Vec<PresenceEvent> {
+        let Some(timeout) = self.timeout else {
+            return Vec::new();
+        };
+        let expired: Vec<String> = {
+            let devices = self.devices.lock().expect("presence poisoned");
+            devices
+                .iter()
+                .filter(|(_, d)| {
+                    d.state == PresenceState::Online
+                        && (now - d.last_seen).to_std().unwrap_or_default() > timeout
+                })
+                .map(|(id, _)| id.clone())
+                .collect()
+        };
+        expired
+            .into_iter()
+            .filter_map(|device_id| {
+                self.record(
+                    &device_id,
+                    PresenceState::Offline,
+                    PresenceSource::Timeout,
+                    None,
+                    now,
+                )
+            })
+            .collect()
+    }
+
+    pub fn get(&self, device_id: &str) -> Option<Presence> {
+        let devices = self.devices.lock().expect("presence poisoned");
+        devices.get(device_id).map(|d| Presence {
+            device_id: device_id.to_string(),
+            state: d.state,
+            since: d.since,
+            last_seen: d.last_seen,
+            history: d.history.iter().cloned().collect(),
+        })
+    }
+}
+
+/// `online`/`offline` as plain text, or JSON with `state` (or `status`) and
+/// an optional `reason`.
+fn parse_status(payload: &Value) -> Option<(PresenceState, Option<String>)> {
+    let (state, reason) = match payload {
+        Value::String(s) => (s.as_str(), None),
+        Value::Object(fields) => (
+            fields
+                .get("state")
+                .or_else(|| fields.get("status"))
+                .and_then(Value::as_str)?,
+            fields
+                .get("reason")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        ),
+        _ => return None,
+    };
+    match state.trim().to_ascii_lowercase().as_str() {
+        "online" | "connected" => Some((PresenceState::Online, reason)),
+        "offline" | "disconnected" => Some((PresenceState::Offline, reason)),
+        _ => None,
+    }
+}
@@ -0,0 +1,202 @@
+use mqtt_client::{Client, ClientError, Properties};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+
+use crate::codec::PayloadFormat;
+use crate::metrics;
+
+/// Resolved when the broker acknowledges a confirmed publish, with the
+/// reason code when an MQTT v5 broker rejected it.
+pub type AckReceiver = oneshot::Receiver<Result<(), String>>;
+
+type Slot = Option<oneshot::Sender<Result<(), String>>>;
+
+#[derive(Default)]
+struct Tracking {
+    /// Publishes handed to the client whose packet id is not known yet, in
+    /// channel order.
+    queued: VecDeque<Slot>,
+    /// Publishes sent to the broker and not yet acknowledged, oldest first.
+    inflight: VecDeque<(u16, Slot)>,
+    /// Publishes rumqttc holds back until an older packet with the same id
+    /// is acknowledged.
+    collided: HashMap<u16, Slot>,
+}
+
+/// QoS 1 publishing for the whole service. rumqttc does not return packet
+/// ids, but it assigns them in channel order and reports each one from the
+/// event loop, so every publish goes through here to keep that order known.
+#[derive(Clone)]
+pub struct Publisher {
+    client: Client,
+    /// Held while handing a publish to the client so channel order matches
+    /// `Tracking::queued`.
+    order: Arc<tokio::sync::Mutex<()>>,
+    tracking: Arc<Mutex<Tracking>>,
+}
+
+impl Publisher {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            order: Arc::default(),
+            tracking: Arc::default(),
+        }
+    }
+
+    async fn send(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        properties: Properties,
+        slot: Slot,
+    ) -> Result<(), ClientError> {
+        let _order = self.order.lock().await;
+        self.tracking
+            .lock()
+            .expect("publish tracking poisoned")
+            .queued
+            .push_back(slot);
+        let result = self.client.publish(topic, payload, properties).await;
+        if result.is_err() {
+            self.unqueue();
+        }
+        metrics::record_publish(&result);
+        result
+    }
+
+    fn unqueue(&self) {
+        self.tracking
+            .lock()
+            .expect("publish tracking poisoned")
+            .queued
+            .pop_back();
+    }
+
+    /// Hand a publish to the client, waiting for space in its request channel.
+    pub async fn publish(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        properties: Properties,
+    ) -> Result<(), ClientError> {
+        self.send(topic, payload, properties, None).await
+    }
+
+    /// Like [`Publisher::publish`], also returning a receiver that resolves
+    /// on the broker's PubAck.
+    pub async fn publish_confirmed(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        properties: Properties,
+    ) -> Result<AckReceiver, ClientError> {
+        let (tx, rx) = oneshot::channel();
+        self.send(topic, payload, properties, Some(tx)).await?;
+        Ok(rx)
+    }
+
+    /// Publish a JSON event without waiting, so it is safe to call from the
+    /// MQTT event loop task; failures are only logged.
+    pub fn publish_event<T: Serialize>(&self, topic: String, event: &T) {
+        let payload = match serde_json::to_vec(event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                tracing::error!(%topic, error = %e, "serialize event failed");
+                return;
+            }
+        };
+        let Ok(_order) = self.order.try_lock() else {
+            // Another publish is waiting for channel space; blocking here
+            // would stall the event loop that frees it
+            let publisher = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = publisher
+                    .publish(topic.clone(), payload, event_properties())
+                    .await
+                {
+                    tracing::error!(%topic, error = %e, "event publish failed");
+                }
+            });
+            return;
+        };
+        self.tracking
+            .lock()
+            .expect("publish tracking poisoned")
+            .queued
+            .push_back(None);
+        let result = self
+            .client
+            .try_publish(topic.clone(), payload, event_properties());
+        if result.is_err() {
+            self.unqueue();
+        }
+        metrics::record_publish(&result);
+        if let Err(e) = result {
+            tracing::error!(%topic, error = %e, "event publish failed");
+        }
+    }
+
+    /// `Outgoing::Publish(pkid)` from the event loop.
+    pub fn on_outgoing_publish(&self, pkid: u16) {
+        let mut tracking = self.tracking.lock().expect("publish tracking poisoned");
+        // Unacknowledged publishes are resent with their id after a reconnect
+        if tracking.inflight.iter().any(|(id, _)| *id == pkid) {
+            return;
+        }
+        let slot = match tracking.collided.remove(&pkid) {
+            Some(slot) => slot,
+            None => tracking.queued.pop_front().flatten(),
+        };
+        tracking.inflight.push_back((pkid, slot));
+    }
+
+    /// `Outgoing::AwaitAck(pkid)`: the publish waits for an older packet id.
+    pub fn on_await_ack(&self, pkid: u16) {
+        let mut tracking = self.tracking.lock().expect("publish tracking poisoned");
+        let slot = tracking.queued.pop_front().flatten();
+        tracking.collided.insert(pkid, slot);
+    }
+
+    /// `Incoming::PubAck(pkid)` from the event loop.
+    pub fn on_puback(&self, pkid: u16) {
+        let mut tracking = self.tracking.lock().expect("publish tracking poisoned");
+        let Some(index) = tracking.inflight.iter().position(|(id, _)| *id == pkid) else {
+            return;
+        };
+        if let Some((_, Some(tx))) = tracking.inflight.remove(index) {
+            // The request may have timed out already
+            let _ = tx.send(Ok(()));
+        }
+    }
+
+    /// An MQTT v5 broker rejected a publish. rumqttc does not report which
+    /// packet, so this is a best guess: the oldest unacknowledged publish
+    /// gets the rejection. With several confirmed publishes in flight it can
+    /// land on the wrong request, and the rejected one then times out.
+    pub fn on_rejected(&self, reason: &str) {
+        let slot = self
+            .tracking
+            .lock()
+            .expect("publish tracking poisoned")
+            .inflight
+            .pop_front();
+        if let Some((_, Some(tx))) = slot {
+            let _ = tx.send(Err(reason.to_string()));
+        }
+    }
+}
+
+/// Service events are JSON.
+fn event_properties() -> Properties {
+    Properties::with_content_type(PayloadFormat::Json.content_type())
+}
+
+/// Forwarded telemetry carries the content type its topic suffix implies.
+pub fn telemetry_properties(topic: &str) -> Properties {
+    Properties::with_content_type(PayloadFormat::from_topic(topic).content_type())
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+use crate::codec::PayloadFormat;
+use crate::topic::matches_filter;
+
+/// Schema keyword naming the device type a schema applies to.
+pub const DEVICE_TYPE_KEYWORD: &str = "x-argus-device-type";
+/// Schema keyword holding an MQTT topic filter the schema applies to.
+pub const TOPIC_KEYWORD: &str = "x-argus-topic";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    pub schema: String,
+    pub instance_path: String,
+    pub message: String,
+}
+
+struct SchemaEntry {
+    name: String,
+    device_type: Option<String>,
+    topic: Option<String>,
+    validator: jsonschema::Validator,
+}
+
+impl SchemaEntry {
+    fn applies_to(&self, device_type: Option<&str>, topic: &str) -> bool {
+        let by_type = self.device_type.is_some() && self.device_type.as_deref() == device_type;
+        let by_topic = self
+            .topic
+            .as_deref()
+            .is_some_and(|filter| matches_filter(filter, topic));
+        by_type || by_topic
+    }
+}
+
+/// Telemetry schemas keyed by device type and/or topic filter.
+#[derive(Default)]
+pub struct SchemaRegistry {
+    entries: Vec<SchemaEntry>,
+}
+
+impl SchemaRegistry {
+    /// Load every `*.json` schema in `dir`.
+    ///
+    /// A schema applies to the device type in `x-argus-device-type` and/or the
+    /// topics matching `x-argus-topic`; with neither, the file stem is used as
+    /// the device type.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut registry = Self::default();
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("failed to read schema dir {}", dir.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let raw = std::fs::read(&path)
+                .with_context(|| format!("failed to read schema {}", path.display()))?;
+            let schema: Value = serde_json::from_slice(&raw)
+                .with_context(|| format!("schema {} is not valid JSON", path.display()))?;
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string();
+            registry
+                .add(&stem, schema)
+                .with_context(|| format!("failed to compile schema {}", path.display()))?;
+        }
+        Ok(registry)
+    }
+
+    pub fn add(&mut self, name: &str, schema: Value) -> Result<()> {
+        let keyword = |key: &str| schema.get(key).and_then(Value::as_str).map(str::to_string);
+        let mut device_type = keyword(DEVICE_TYPE_KEYWORD);
+        let topic = keyword(TOPIC_KEYWORD);
+        if device_type.is_none() && topic.is_none() {
+            device_type = Some(name.to_string());
+        }
+        let validator = match jsonschema::validator_for(&schema) {
+            Ok(v) => v,
+            Err(e) => bail!("{e}"),
+        };
+        self.entries.push(SchemaEntry {
+            name: name.to_string(),
+            device_type,
+            topic,
+            validator,
+        });
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Validate `instance` against every schema that applies to it.
+    ///
+    /// The device type is read from `device_type` or `tags.device_type`.
+    pub fn validate(&self, topic: &str, instance: &Value) -> Vec<Violation> {
+        let device_type = device_type_of(instance);
+        self.entries
+            .iter()
+            .filter(|entry| entry.applies_to(device_type, topic))
+            .flat_map(|entry| {
+                entry
+                    .validator
+                    .iter_errors(instance)
+                    .map(|err| Violation {
+                        schema: entry.name.clone(),
+                        instance_path: err.instance_path.to_string(),
+                        message: err.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Like [`validate`](Self::validate) for raw MQTT payloads in `format`; a
+    /// payload that does not decode only fails when some topic schema applies
+    /// to it. Topic schemas match the topic without its encoding suffix.
+    pub fn validate_payload(
+        &self,
+        topic: &str,
+        format: PayloadFormat,
+        payload: &[u8],
+    ) -> Vec<Violation> {
+        let topic = PayloadFormat::canonical_topic(topic);
+        match format.decode(payload) {
+            // SenML packs resolve to several documents; check each one
+            Ok(Value::Array(docs)) if format.is_senml() => docs
+                .iter()
+                .enumerate()
+                .flat_map(|(i, doc)| {
+                    self.validate(topic, doc)
+                        .into_iter()
+                        .map(move |v| Violation {
+                            instance_path: format!("/{i}{}", v.instance_path),
+                            ..v
+                        })
+                })
+                .collect(),
+            Ok(instance) => self.validate(topic, &instance),
+            Err(e) => self
+                .entries
+                .iter()
+                .filter(|entry| entry.applies_to(None, topic))
+                .map(|entry| Violation {
+                    schema: entry.name.clone(),
+                    instance_path: String::new(),
+                    message: format!("payload is not {}: {e}", format.name().to_uppercase()),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn device_type_of(instance: &Value) -> Option<&str> {
+    instance
+        .get("device_type")
+        .or_else(|| instance.get("tags").and_then(|t| t.get("device_type")))
+        .and_then(Value::as_str)
+}
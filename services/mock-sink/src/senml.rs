@@ -0,0 +1,245 @@
+use anyhow::{Result, bail};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Number, Value, json};
+use std::collections::BTreeMap;
+
+use crate::store::StoredMessage;
+use crate::types::MetricValue;
+
+/// Times below 2**28 seconds are relative to now (RFC 8428 section 4.5.3).
+const RELATIVE_TIME_LIMIT: f64 = 268_435_456.0;
+
+/// Integer labels used by SenML CBOR (RFC 8428 table 4).
+const CBOR_LABELS: &[(i64, &str)] = &[
+    (-1, "bver"),
+    (-2, "bn"),
+    (-3, "bt"),
+    (-4, "bu"),
+    (-5, "bv"),
+    (-6, "bs"),
+    (0, "n"),
+    (1, "u"),
+    (2, "v"),
+    (3, "vs"),
+    (4, "vb"),
+    (5, "s"),
+    (6, "t"),
+    (7, "ut"),
+    (8, "vd"),
+];
+
+#[derive(Debug, Default, Deserialize)]
+struct Record {
+    bn: Option<String>,
+    bt: Option<f64>,
+    bu: Option<String>,
+    bv: Option<f64>,
+    bs: Option<f64>,
+    n: Option<String>,
+    u: Option<String>,
+    v: Option<Number>,
+    vs: Option<String>,
+    vb: Option<bool>,
+    vd: Option<String>,
+    s: Option<f64>,
+    t: Option<f64>,
+}
+
+/// One fully resolved SenML record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    /// Device part of the resolved name, if the name has one.
+    pub device_id: Option<String>,
+    pub measurement: String,
+    pub unit: Option<String>,
+    /// Absolute time in (fractional) seconds since the epoch.
+    pub time: f64,
+    pub value: MetricValue,
+}
+
+/// Resolve base name, time, unit and value of every record in `pack`.
+///
+/// Accepts both string labels (SenML JSON) and the integer labels of SenML
+/// CBOR once decoded to JSON.
+pub fn resolve(pack: Value, now: DateTime<Utc>) -> Result<Vec<Resolved>> {
+    let Value::Array(items) = pack else {
+        bail!("SenML pack must be an array");
+    };
+    let now = now.timestamp_millis() as f64 / 1000.0;
+    let mut base = Record::default();
+    let mut resolved = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        let Value::Object(fields) = item else {
+            bail!("SenML record {index} is not an object");
+        };
+        let record: Record = serde_json::from_value(Value::Object(with_names(fields)))
+            .map_err(|e| anyhow::anyhow!("SenML record {index}: {e}"))?;
+
+        // Base fields apply to this and every later record until replaced.
+        if record.bn.is_some() {
+            base.bn = record.bn.clone();
+        }
+        if record.bt.is_some() {
+            base.bt = record.bt;
+        }
+        if record.bu.is_some() {
+            base.bu = record.bu.clone();
+        }
+        if record.bv.is_some() {
+            base.bv = record.bv;
+        }
+        if record.bs.is_some() {
+            base.bs = record.bs;
+        }
+
+        let name = format!(
+            "{}{}",
+            base.bn.as_deref().unwrap_or_default(),
+            record.n.as_deref().unwrap_or_default()
+        );
+        if name.is_empty() {
+            bail!("SenML record {index} has no name");
+        }
+        let value = match (&record.v, record.vs, record.vb, record.vd, record.s) {
+            (Some(v), ..) => match base.bv {
+                Some(bv) => number(v.as_f64().unwrap_or_default() + bv)?,
+                None => MetricValue::Number(v.clone()),
+            },
+            (None, Some(vs), ..) => MetricValue::Text(vs),
+            (None, None, Some(vb), ..) => MetricValue::Bool(vb),
+            (None, None, None, Some(vd), _) => MetricValue::Text(vd),
+            (None, None, None, None, Some(s)) => number(s + base.bs.unwrap_or_default())?,
+            _ => bail!("SenML record {index} has no value"),
+        };
+        let mut time = base.bt.unwrap_or_default() + record.t.unwrap_or_default();
+        if time < RELATIVE_TIME_LIMIT {
+            time += now;
+        }
+        let (device_id, measurement) = split_name(&name);
+        resolved.push(Resolved {
+            device_id,
+            measurement,
+            unit: record.u.or_else(|| base.bu.clone()),
+            time,
+            value,
+        });
+    }
+    Ok(resolved)
+}
+
+/// Group resolved records into telemetry documents, one per device and
+/// millisecond; `ts_ms` keeps sub-second records apart.
+pub fn to_telemetry(records: Vec<Resolved>) -> Vec<Value> {
+    let mut groups: Vec<(Option<String>, i64, TelemetryGroup)> = Vec::new();
+    for record in records {
+        let millis = (record.time * 1000.0).round() as i64;
+        let idx = match groups
+            .iter()
+            .position(|(device, at, _)| *device == record.device_id && *at == millis)
+        {
+            Some(idx) => idx,
+            None => {
+                groups.push((record.device_id.clone(), millis, TelemetryGroup::default()));
+                groups.len() - 1
+            }
+        };
+        let group = &mut groups[idx].2;
+        if let Some(unit) = record.unit {
+            group.units.insert(record.measurement.clone(), unit);
+        }
+        group.metrics.insert(record.measurement, record.value);
+    }
+    groups
+        .into_iter()
+        .map(|(device_id, millis, group)| {
+            let mut doc = Map::new();
+            if let Some(device_id) = device_id {
+                doc.insert("device_id".into(), device_id.into());
+            }
+            doc.insert("ts".into(), millis.div_euclid(1000).into());
+            doc.insert("ts_ms".into(), millis.into());
+            doc.insert("metrics".into(), json!(group.metrics));
+            if !group.units.is_empty() {
+                doc.insert("units".into(), json!(group.units));
+            }
+            Value::Object(doc)
+        })
+        .collect()
+}
+
+/// Re-emit stored telemetry as a SenML JSON pack: one base name and time per
+/// reading, followed by a record per metric.
+pub fn to_pack(messages: &[StoredMessage]) -> Vec<Value> {
+    let mut pack = Vec::new();
+    for message in messages {
+        for reading in message.readings() {
+            let time = match (reading.ts_ms, reading.ts) {
+                (Some(ms), _) => ms as f64 / 1000.0,
+                (None, Some(ts)) => ts as f64,
+                (None, None) => message.received_at.timestamp_millis() as f64 / 1000.0,
+            };
+            for (i, (name, value)) in reading.resolved_metrics().into_iter().enumerate() {
+                let mut record = Map::new();
+                if i == 0 {
+                    record.insert("bn".into(), format!("{}/", reading.device_id).into());
+                    record.insert("bt".into(), json!(time));
+                }
+                record.insert("n".into(), name.clone().into());
+                if let Some(unit) = reading.units.get(&name) {
+                    record.insert("u".into(), unit.clone().into());
+                }
+                let (label, value) = match value {
+                    MetricValue::Number(n) => ("v", Value::Number(n)),
+                    MetricValue::Text(s) => ("vs", Value::String(s)),
+                    MetricValue::Bool(b) => ("vb", Value::Bool(b)),
+                };
+                record.insert(label.into(), value);
+                pack.push(Value::Object(record));
+            }
+        }
+    }
+    pack
+}
+
+#[derive(Default)]
+struct TelemetryGroup {
+    metrics: BTreeMap<String, MetricValue>,
+    units: BTreeMap<String, String>,
+}
+
+fn number(value: f64) -> Result<MetricValue> {
+    Number::from_f64(value)
+        .map(MetricValue::Number)
+        .ok_or_else(|| anyhow::anyhow!("non-finite SenML value"))
+}
+
+/// Replace SenML CBOR integer labels with their string names.
+fn with_names(fields: Map<String, Value>) -> Map<String, Value> {
+    fields
+        .into_iter()
+        .map(|(key, value)| {
+            let name = key
+                .parse::<i64>()
+                .ok()
+                .and_then(|label| CBOR_LABELS.iter().find(|(l, _)| *l == label))
+                .map_or(key, |(_, name)| (*name).to_string());
+            (name, value)
+        })
+        .collect()
+}
+
+/// Split `device/measurement` or `[urn:dev:<type>:]device:measurement`.
+fn split_name(name: &str) -> (Option<String>, String) {
+    let unprefixed = name
+        .strip_prefix("urn:dev:")
+        .and_then(|rest| rest.split_once(':'))
+        .map_or(name, |(_, rest)| rest);
+    match unprefixed.rfind(['/', ':']) {
+        Some(idx) if idx > 0 && idx + 1 < unprefixed.len() => (
+            Some(unprefixed[..idx].to_string()),
+            unprefixed[idx + 1..].to_string(),
+        ),
+        _ => (None, name.to_string()),
+    }
+}
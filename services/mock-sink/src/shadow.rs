@@ -0,0 +1,252 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use topic_layout::TopicLayout;
+
+use crate::handlers::AppState;
+use crate::publisher::Publisher;
+
+/// Desired and reported configuration for one device.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Shadow {
+    pub device_id: String,
+    /// Bumped on every change to either document.
+    pub version: u64,
+    pub desired: Map<String, Value>,
+    pub reported: Map<String, Value>,
+    /// Desired values the device has not reported yet.
+    pub delta: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_updated_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reported_updated_at: Option<DateTime<Utc>>,
+}
+
+// Published on the shadow topic plus /delta, {prefix}{device_id}/shadow/delta by default
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeltaEvent {
+    pub version: u64,
+    pub delta: Map<String, Value>,
+    pub timestamp: DateTime<Utc>,
+}
+
+// Body of PATCH /devices/{device_id}/shadow
+#[derive(Debug, Deserialize)]
+pub struct ShadowUpdate {
+    #[serde(default)]
+    pub desired: Option<Map<String, Value>>,
+    #[serde(default)]
+    pub reported: Option<Map<String, Value>>,
+    /// Reject the update with a conflict unless the shadow is at this version.
+    pub version: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    Desired,
+    Reported,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShadowError {
+    VersionConflict { current: u64 },
+}
+
+/// Shadows keyed by device id.
+#[derive(Default)]
+pub struct ShadowStore {
+    shadows: Mutex<HashMap<String, Shadow>>,
+}
+
+impl ShadowStore {
+    pub fn get(&self, device_id: &str) -> Option<Shadow> {
+        let shadows = self.shadows.lock().expect("shadows poisoned");
+        shadows.get(device_id).cloned()
+    }
+
+    /// Merge `patch` into one document (`null` removes a key) and recompute
+    /// the delta; returns the shadow and a delta event when the delta changed
+    /// and is not empty.
+    pub fn update(
+        &self,
+        device_id: &str,
+        document: Document,
+        patch: &Map<String, Value>,
+        expected_version: Option<u64>,
+        now: DateTime<Utc>,
+    ) -> Result<(Shadow, Option<DeltaEvent>), ShadowError> {
+        let mut shadows = self.shadows.lock().expect("shadows poisoned");
+        let shadow = shadows
+            .entry(device_id.to_string())
+            .or_insert_with(|| Shadow {
+                device_id: device_id.to_string(),
+                ..Default::default()
+            });
+        if let Some(expected) = expected_version
+            && expected != shadow.version
+        {
+            return Err(ShadowError::VersionConflict {
+                current: shadow.version,
+            });
+        }
+        match document {
+            Document::Desired => {
+                merge(&mut shadow.desired, patch);
+                shadow.desired_updated_at = Some(now);
+            }
+            Document::Reported => {
+                merge(&mut shadow.reported, patch);
+                shadow.reported_updated_at = Some(now);
+            }
+        }
+        shadow.version += 1;
+        let delta = delta(&shadow.desired, &shadow.reported);
+        let changed = delta != shadow.delta;
+        shadow.delta = delta;
+        let event = (changed && !shadow.delta.is_empty()).then(|| DeltaEvent {
+            version: shadow.version,
+            delta: shadow.delta.clone(),
+            timestamp: now,
+        });
+        Ok((shadow.clone(), event))
+    }
+}
+
+/// JSON merge patch: objects merge recursively and `null` deletes.
+pub fn merge(target: &mut Map<String, Value>, patch: &Map<String, Value>) {
+    for (key, value) in patch {
+        match (target.get_mut(key), value) {
+            (_, Value::Null) => {
+                target.remove(key);
+            }
+            (Some(Value::Object(existing)), Value::Object(nested)) => merge(existing, nested),
+            (_, Value::Object(nested)) => {
+                let mut fresh = Map::new();
+                merge(&mut fresh, nested);
+                target.insert(key.clone(), Value::Object(fresh));
+            }
+            _ => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Desired entries that differ from what was reported, recursing into objects.
+pub fn delta(desired: &Map<String, Value>, reported: &Map<String, Value>) -> Map<String, Value> {
+    let mut out = Map::new();
+    for (key, want) in desired {
+        match (want, reported.get(key)) {
+            (Value::Object(want), Some(Value::Object(have))) => {
+                let nested = delta(want, have);
+                if !nested.is_empty() {
+                    out.insert(key.clone(), Value::Object(nested));
+                }
+            }
+            (want, Some(have)) if want == have => {}
+            (want, _) => {
+                out.insert(key.clone(), want.clone());
+            }
+        }
+    }
+    out
+}
+
+fn publish_delta(
+    mqtt: &Publisher,
+    topics: &TopicLayout,
+    device_id: &str,
+    event: Option<DeltaEvent>,
+) {
+    let Some(event) = event else { return };
+    match topics.shadow_delta_topic(device_id) {
+        Ok(topic) => {
+            tracing::info!(%device_id, version = event.version, "shadow delta published");
+            mqtt.publish_event(topic, &event);
+        }
+        Err(e) => tracing::warn!(%device_id, "shadow delta not published: {e}"),
+    }
+}
+
+/// Apply a device's shadow `reported` message, either
+/// `{"reported": {...}, "version": n}` or the reported document itself, and
+/// publish the new delta.
+pub fn handle_reported(
+    shadows: &ShadowStore,
+    mqtt: &Publisher,
+    topics: &TopicLayout,
+    device_id: &str,
+    payload: &Value,
+) {
+    let Value::Object(fields) = payload else {
+        tracing::warn!(%device_id, "shadow report is not a JSON object");
+        return;
+    };
+    let (patch, version) = match fields.get("reported") {
+        Some(Value::Object(reported)) => (reported, fields.get("version").and_then(Value::as_u64)),
+        _ => (fields, None),
+    };
+    match shadows.update(device_id, Document::Reported, patch, version, Utc::now()) {
+        Ok((shadow, event)) => {
+            tracing::info!(%device_id, version = shadow.version, "shadow reported state updated");
+            publish_delta(mqtt, topics, device_id, event);
+        }
+        Err(ShadowError::VersionConflict { current }) => {
+            tracing::warn!(%device_id, current, "shadow report rejected: version conflict");
+        }
+    }
+}
+
+pub async fn get_shadow(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+) -> Result<Json<Shadow>, (StatusCode, String)> {
+    state
+        .shadows
+        .get(&device_id)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "no shadow for device".into()))
+}
+
+pub async fn update_shadow(
+    State(state): State<Arc<AppState>>,
+    Path(device_id): Path<String>,
+    Json(req): Json<ShadowUpdate>,
+) -> Result<Json<Shadow>, (StatusCode, String)> {
+    if req.reported.is_some() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "reported state is only set by the device".into(),
+        ));
+    }
+    let desired = req
+        .desired
+        .ok_or((StatusCode::BAD_REQUEST, "missing 'desired'".into()))?;
+    let (shadow, event) = state
+        .shadows
+        .update(
+            &device_id,
+            Document::Desired,
+            &desired,
+            req.version,
+            Utc::now(),
+        )
+        .map_err(|ShadowError::VersionConflict { current }| {
+            (
+                StatusCode::CONFLICT,
+                format!("version conflict: shadow is at version {current}"),
+            )
+        })?;
+    tracing::info!(%device_id, version = shadow.version, "shadow desired state updated");
+    publish_delta(&state.mqtt, &state.topics, &device_id, event);
+    Ok(Json(shadow))
+}
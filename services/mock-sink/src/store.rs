@@ -0,0 +1,565 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use mqtt_client::{Message, Properties};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use topic_layout::TopicLayout;
+
+use crate::codec::PayloadFormat;
+use crate::schema::Violation;
+use crate::topic::{is_wildcard, matches_filter};
+use crate::types::TelemetryIn;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A consumed MQTT message as persisted in the store.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredMessage {
+    pub id: i64,
+    pub topic: String,
+    pub device_id: Option<String>,
+    /// Decoded payload; binary encodings are shown in their JSON form.
+    pub payload: Value,
+    /// Wire encoding when it was not JSON (`cbor`, `msgpack`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    pub received_at: DateTime<Utc>,
+    /// Schema violations; non-empty marks the message invalid.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<Violation>,
+    /// MQTT v5 publish properties, when the message carried any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<MessageProperties>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_topic: Option<String>,
+    /// Correlation data as (lossy) UTF-8.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_data: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_properties: Vec<(String, String)>,
+    /// Expiry interval left when the broker delivered it, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_expiry: Option<u32>,
+}
+
+impl MessageProperties {
+    /// `None` for a message without properties (always so over MQTT 3.1.1).
+    pub fn from_mqtt(properties: &Properties) -> Option<Self> {
+        (!properties.is_empty()).then(|| Self {
+            content_type: properties.content_type.clone(),
+            response_topic: properties.response_topic.clone(),
+            correlation_data: properties
+                .correlation_data
+                .as_deref()
+                .map(|data| String::from_utf8_lossy(data).into_owned()),
+            user_properties: properties.user_properties.clone(),
+            message_expiry: properties.message_expiry,
+        })
+    }
+}
+
+impl StoredMessage {
+    /// Telemetry readings in the payload (several for a resolved SenML pack);
+    /// documents without a `device_id` belong to the topic's device.
+    pub fn readings(&self) -> Vec<TelemetryIn> {
+        let docs = match &self.payload {
+            Value::Array(items) => items.clone(),
+            other => vec![other.clone()],
+        };
+        docs.into_iter()
+            .filter_map(|mut doc| {
+                if let (Some(fields), Some(device_id)) = (doc.as_object_mut(), &self.device_id) {
+                    fields
+                        .entry("device_id")
+                        .or_insert_with(|| device_id.clone().into());
+                }
+                serde_json::from_value(doc).ok()
+            })
+            .collect()
+    }
+}
+
+/// Per-device activity summary for `GET /devices`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSummary {
+    pub device_id: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub message_count: u64,
+}
+
+/// Filter for [`Store::device_messages`].
+#[derive(Debug, Clone, Default)]
+pub struct MessageQuery {
+    pub device_id: String,
+    /// Only messages on one of these topics; empty matches every topic.
+    pub topics: Vec<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub limit: u32,
+}
+
+/// Filter for [`Store::received`].
+#[derive(Debug, Clone, Default)]
+pub struct ReceivedQuery {
+    /// MQTT topic filter; wildcards follow subscription semantics.
+    pub topic: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    /// Substring the (UTF-8) payload must contain.
+    pub contains: Option<String>,
+    pub limit: usize,
+}
+
+impl ReceivedQuery {
+    pub fn matches(&self, message: &StoredMessage) -> bool {
+        if let Some(filter) = &self.topic
+            && !matches_filter(filter, &message.topic)
+        {
+            return false;
+        }
+        // Stored timestamps have millisecond precision.
+        if let Some(since) = self.since
+            && message.received_at.timestamp_millis() < since.timestamp_millis()
+        {
+            return false;
+        }
+        match &self.contains {
+            Some(needle) => match &message.payload {
+                Value::String(text) => text.contains(needle.as_str()),
+                other => other.to_string().contains(needle.as_str()),
+            },
+            None => true,
+        }
+    }
+}
+
+/// Limits applied by [`Store::prune`]; `None` disables that limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retention {
+    pub max_age: Option<Duration>,
+    pub max_messages: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct Store {
+    conn: Arc<Mutex<Connection>>,
+    retention: Retention,
+}
+
+/// Rows read per query by [`Store::received`].
+const RECEIVED_PAGE_SIZE: i64 = 500;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    topic       TEXT NOT NULL,
+    device_id   TEXT,
+    payload     BLOB NOT NULL,
+    received_at INTEGER NOT NULL,
+    violations  TEXT,
+    encoding    TEXT,
+    properties  TEXT
+);
+CREATE INDEX IF NOT EXISTS messages_device_time ON messages (device_id, received_at);
+CREATE INDEX IF NOT EXISTS messages_time ON messages (received_at);
+";
+
+impl Store {
+    pub fn open(path: &Path, retention: Retention) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create store directory {}", parent.display())
+            })?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open store at {}", path.display()))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn, retention)
+    }
+
+    pub fn open_in_memory(retention: Retention) -> Result<Self> {
+        Self::init(Connection::open_in_memory()?, retention)
+    }
+
+    fn init(conn: Connection, retention: Retention) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("failed to initialise store schema")?;
+        migrate(&conn).context("failed to migrate store schema")?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            retention,
+        })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().expect("store mutex poisoned");
+            f(&conn)
+        })
+        .await?
+        .map_err(Into::into)
+    }
+
+    pub async fn insert(
+        &self,
+        consumed: &Message,
+        device_id: Option<&str>,
+        format: PayloadFormat,
+        received_at: DateTime<Utc>,
+        violations: Vec<Violation>,
+    ) -> Result<StoredMessage> {
+        let encoding = (format != PayloadFormat::Json).then(|| format.name().to_string());
+        let mut message = StoredMessage {
+            id: 0,
+            topic: consumed.topic.clone(),
+            device_id: device_id.map(str::to_string),
+            payload: decode_payload(&consumed.payload, encoding.as_deref(), received_at),
+            encoding,
+            received_at: millis_to_utc(received_at.timestamp_millis()),
+            violations,
+            properties: MessageProperties::from_mqtt(&consumed.properties),
+        };
+        let violations = (!message.violations.is_empty())
+            .then(|| serde_json::to_string(&message.violations))
+            .transpose()?;
+        let properties = message
+            .properties
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let (topic, device_id, payload, encoding) = (
+            message.topic.clone(),
+            message.device_id.clone(),
+            consumed.payload.to_vec(),
+            message.encoding.clone(),
+        );
+        message.id = self
+            .with_conn(move |conn| {
+                conn.execute(
+                    "INSERT INTO messages (topic, device_id, payload, received_at, violations, encoding, properties)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        topic,
+                        device_id,
+                        payload,
+                        received_at.timestamp_millis(),
+                        violations,
+                        encoding,
+                        properties
+                    ],
+                )?;
+                Ok(conn.last_insert_rowid())
+            })
+            .await?;
+        Ok(message)
+    }
+
+    /// Messages for a device ordered by receive time (oldest first).
+    ///
+    /// Without a `from` bound the newest `limit` messages are returned.
+    pub async fn device_messages(&self, query: MessageQuery) -> Result<Vec<StoredMessage>> {
+        self.with_conn(move |conn| {
+            let mut sql = String::from(
+                "SELECT id, topic, device_id, payload, received_at, violations, encoding, properties
+                 FROM messages WHERE device_id = ?",
+            );
+            let mut args: Vec<rusqlite::types::Value> = vec![query.device_id.into()];
+            if !query.topics.is_empty() {
+                sql.push_str(" AND topic IN (");
+                sql.push_str(&vec!["?"; query.topics.len()].join(", "));
+                sql.push(')');
+                args.extend(query.topics.into_iter().map(Into::into));
+            }
+            if let Some(from) = query.from {
+                sql.push_str(" AND received_at >= ?");
+                args.push(from.timestamp_millis().into());
+            }
+            if let Some(to) = query.to {
+                sql.push_str(" AND received_at <= ?");
+                args.push(to.timestamp_millis().into());
+            }
+            let newest_first = query.from.is_none();
+            sql.push_str(if newest_first {
+                " ORDER BY received_at DESC, id DESC LIMIT ?"
+            } else {
+                " ORDER BY received_at ASC, id ASC LIMIT ?"
+            });
+            args.push(i64::from(query.limit).into());
+
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt
+                .query_map(rusqlite::params_from_iter(args), row_to_message)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            if newest_first {
+                rows.reverse();
+            }
+            Ok(rows)
+        })
+        .await
+    }
+
+    /// Messages matching `query`, oldest first. Rows are read a page at a
+    /// time, narrowed in SQL by `since` and the topic filter's literal levels.
+    pub async fn received(&self, query: ReceivedQuery) -> Result<Vec<StoredMessage>> {
+        let (exact_topic, topic_prefix) = match query.topic.as_deref() {
+            Some(filter) if is_wildcard(filter) => (None, Some(literal_prefix(filter))),
+            Some(topic) => (Some(topic.to_string()), None),
+            None => (None, None),
+        };
+        let since = query.since.map(|t| t.timestamp_millis());
+        let mut found = Vec::new();
+        let mut after: Option<(i64, i64)> = None;
+        loop {
+            let (exact_topic, topic_prefix) = (exact_topic.clone(), topic_prefix.clone());
+            let page = self
+                .with_conn(move |conn| {
+                    let mut stmt = conn.prepare(
+                        "SELECT id, topic, device_id, payload, received_at, violations, encoding, properties FROM messages
+                         WHERE (?1 IS NULL OR received_at >= ?1)
+                           AND (?2 IS NULL OR topic = ?2)
+                           AND (?3 IS NULL OR substr(topic, 1, length(?3)) = ?3)
+                           AND (?4 IS NULL OR (received_at, id) > (?4, ?5))
+                         ORDER BY received_at ASC, id ASC
+                         LIMIT ?6",
+                    )?;
+                    stmt.query_map(
+                        params![
+                            since,
+                            exact_topic,
+                            topic_prefix,
+                            after.map(|(at, _)| at),
+                            after.map(|(_, id)| id),
+                            RECEIVED_PAGE_SIZE
+                        ],
+                        row_to_message,
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .await?;
+            let exhausted = page.len() < RECEIVED_PAGE_SIZE as usize;
+            after = page
+                .last()
+                .map(|m| (m.received_at.timestamp_millis(), m.id));
+            found.extend(page.into_iter().filter(|m| query.matches(m)));
+            if exhausted || found.len() >= query.limit {
+                found.truncate(query.limit);
+                return Ok(found);
+            }
+        }
+    }
+
+    pub async fn latest(
+        &self,
+        device_id: &str,
+        topics: Vec<String>,
+    ) -> Result<Option<StoredMessage>> {
+        let mut rows = self
+            .device_messages(MessageQuery {
+                device_id: device_id.to_string(),
+                topics,
+                limit: 1,
+                ..Default::default()
+            })
+            .await?;
+        Ok(rows.pop())
+    }
+
+    /// Distinct topics stored for a device.
+    pub async fn device_topics(&self, device_id: &str) -> Result<Vec<String>> {
+        let device_id = device_id.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT topic FROM messages WHERE device_id = ?1 ORDER BY topic",
+            )?;
+            stmt.query_map(params![device_id], |row| row.get(0))?
+                .collect()
+        })
+        .await
+    }
+
+    pub async fn devices(&self) -> Result<Vec<DeviceSummary>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT device_id, MIN(received_at), MAX(received_at), COUNT(*)
+                 FROM messages WHERE device_id IS NOT NULL
+                 GROUP BY device_id ORDER BY device_id",
+            )?;
+            stmt.query_map([], |row| {
+                Ok(DeviceSummary {
+                    device_id: row.get(0)?,
+                    first_seen: millis_to_utc(row.get(1)?),
+                    last_seen: millis_to_utc(row.get(2)?),
+                    message_count: row.get(3)?,
+                })
+            })?
+            .collect()
+        })
+        .await
+    }
+
+    /// Most recent messages that failed schema validation, newest first.
+    pub async fn invalid(&self, limit: u32) -> Result<Vec<StoredMessage>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, topic, device_id, payload, received_at, violations, encoding, properties FROM messages
+                 WHERE violations IS NOT NULL ORDER BY id DESC LIMIT ?1",
+            )?;
+            stmt.query_map(params![limit], row_to_message)?.collect()
+        })
+        .await
+    }
+
+    pub async fn count_invalid(&self) -> Result<u64> {
+        self.with_conn(|conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM messages WHERE violations IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+        })
+        .await
+    }
+
+    pub async fn count(&self) -> Result<u64> {
+        self.with_conn(|conn| conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0)))
+            .await
+    }
+
+    /// Apply the retention limits, returning how many messages were removed.
+    pub async fn prune(&self, now: DateTime<Utc>) -> Result<usize> {
+        let retention = self.retention;
+        self.with_conn(move |conn| {
+            let mut removed = 0;
+            if let Some(max_age) = retention.max_age {
+                let cutoff = now.timestamp_millis() - max_age.as_millis() as i64;
+                removed += conn.execute(
+                    "DELETE FROM messages WHERE received_at < ?1",
+                    params![cutoff],
+                )?;
+            }
+            if let Some(max_messages) = retention.max_messages {
+                removed += conn.execute(
+                    "DELETE FROM messages WHERE id NOT IN
+                        (SELECT id FROM messages ORDER BY id DESC LIMIT ?1)",
+                    params![max_messages as i64],
+                )?;
+            }
+            Ok(removed)
+        })
+        .await
+    }
+}
+
+/// Bring stores created by older builds up to the current schema.
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let has_violations = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'violations'")?
+        .exists([])?;
+    if !has_violations {
+        conn.execute("ALTER TABLE messages ADD COLUMN violations TEXT", [])?;
+    }
+    let has_encoding = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'encoding'")?
+        .exists([])?;
+    if !has_encoding {
+        conn.execute("ALTER TABLE messages ADD COLUMN encoding TEXT", [])?;
+    }
+    let has_properties = conn
+        .prepare("SELECT 1 FROM pragma_table_info('messages') WHERE name = 'properties'")?
+        .exists([])?;
+    if !has_properties {
+        conn.execute("ALTER TABLE messages ADD COLUMN properties TEXT", [])?;
+    }
+    Ok(())
+}
+
+fn row_to_message(row: &rusqlite::Row<'_>) -> rusqlite::Result<StoredMessage> {
+    let payload: Vec<u8> = row.get("payload")?;
+    let encoding: Option<String> = row.get("encoding")?;
+    let received_at = millis_to_utc(row.get("received_at")?);
+    Ok(StoredMessage {
+        id: row.get("id")?,
+        topic: row.get("topic")?,
+        device_id: row.get("device_id")?,
+        payload: decode_payload(&payload, encoding.as_deref(), received_at),
+        encoding,
+        received_at,
+        violations: row
+            .get::<_, Option<String>>("violations")?
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default(),
+        properties: row
+            .get::<_, Option<String>>("properties")?
+            .and_then(|raw| serde_json::from_str(&raw).ok()),
+    })
+}
+
+fn millis_to_utc(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+}
+
+/// Topic levels before the first wildcard, e.g. `argus/devices` for
+/// `argus/devices/+/status`; every matching topic starts with it.
+fn literal_prefix(filter: &str) -> String {
+    filter
+        .split('/')
+        .take_while(|level| *level != "+" && *level != "#")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decode the raw payload in its recorded `encoding` (JSON when `None`);
+/// anything undecodable is returned as a (lossy) UTF-8 string. Relative SenML
+/// times resolve against `received_at`, so every read gives the same result.
+fn decode_payload(payload: &[u8], encoding: Option<&str>, received_at: DateTime<Utc>) -> Value {
+    encoding
+        .and_then(PayloadFormat::from_name)
+        .unwrap_or(PayloadFormat::Json)
+        .decode_at(payload, received_at)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()))
+}
+
+/// `{device_id}` of a telemetry topic, in any encoding.
+pub fn telemetry_device<'a>(topics: &TopicLayout, topic: &'a str) -> Option<&'a str> {
+    topics.telemetry().device_id(topic).or_else(|| {
+        topics
+            .telemetry()
+            .device_id(PayloadFormat::canonical_topic(topic))
+    })
+}
+
+/// Whether `topic` carries telemetry readings for `device_id`.
+pub fn is_telemetry(topics: &TopicLayout, topic: &str, device_id: &str) -> bool {
+    telemetry_device(topics, topic) == Some(device_id)
+}
+
+/// Extract `{device_id}` from a telemetry, status, command or shadow topic,
+/// or from `{prefix}{device_id}[/...]`.
+pub fn device_id_from_topic<'a>(topics: &TopicLayout, topic: &'a str) -> Option<&'a str> {
+    telemetry_device(topics, topic)
+        .or_else(|| topics.device_id(topic))
+        .or_else(|| {
+            topic
+                .strip_prefix(topics.prefix())?
+                .split('/')
+                .next()
+                .filter(|id| !id.is_empty())
+        })
+}
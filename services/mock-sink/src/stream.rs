@@ -0,0 +1,128 @@
+use axum::{
+    extract::{
+        Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use serde::Deserialize;
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::broadcast;
+use tokio_stream::{
+    Stream, StreamExt,
+    wrappers::{BroadcastStream, errors::BroadcastStreamRecvError},
+};
+
+use crate::handlers::AppState;
+use crate::store::StoredMessage;
+use crate::topic::{is_valid_filter, matches_filter};
+
+// Query string for GET /stream and /ws
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StreamFilter {
+    pub device_id: Option<String>,
+    /// MQTT topic filter; `+`/`#` follow subscription semantics.
+    pub topic: Option<String>,
+}
+
+impl StreamFilter {
+    fn validate(&self) -> Result<(), (StatusCode, String)> {
+        match &self.topic {
+            Some(topic) if !is_valid_filter(topic) => Err((
+                StatusCode::BAD_REQUEST,
+                format!("invalid topic filter '{topic}'"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, message: &StoredMessage) -> bool {
+        if let Some(device_id) = &self.device_id
+            && message.device_id.as_deref() != Some(device_id.as_str())
+        {
+            return false;
+        }
+        self.topic
+            .as_deref()
+            .is_none_or(|filter| matches_filter(filter, &message.topic))
+    }
+}
+
+/// Consumed messages matching `filter`, as SSE events; a `lagged` event
+/// reports how many were dropped when the client falls behind.
+fn events(
+    rx: broadcast::Receiver<StoredMessage>,
+    filter: StreamFilter,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    BroadcastStream::new(rx).filter_map(move |item| match item {
+        Ok(message) if filter.matches(&message) => Some(Ok(Event::default()
+            .event("message")
+            .id(message.id.to_string())
+            .json_data(&message)
+            .unwrap_or_else(|_| Event::default().comment("unserialisable message")))),
+        Ok(_) => None,
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => Some(Ok(Event::default()
+            .event("lagged")
+            .data(skipped.to_string()))),
+    })
+}
+
+pub async fn sse(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<StreamFilter>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    filter.validate()?;
+    tracing::info!(device_id = ?filter.device_id, topic = ?filter.topic, "sse stream opened");
+    let rx = state.events.subscribe();
+    Ok(Sse::new(events(rx, filter)).keep_alive(KeepAlive::default()))
+}
+
+pub async fn ws(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<StreamFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    if let Err(rejection) = filter.validate() {
+        return rejection.into_response();
+    }
+    tracing::info!(device_id = ?filter.device_id, topic = ?filter.topic, "websocket stream opened");
+    let rx = state.events.subscribe();
+    upgrade.on_upgrade(move |socket| forward(socket, rx, filter))
+}
+
+/// Send each matching message as a JSON text frame until the client leaves.
+async fn forward(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<StoredMessage>,
+    filter: StreamFilter,
+) {
+    loop {
+        let frame = tokio::select! {
+            received = rx.recv() => match received {
+                Ok(message) if filter.matches(&message) => serde_json::to_string(&message)
+                    .map(Message::Text)
+                    .ok(),
+                Ok(_) => None,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => Some(Message::Text(
+                    serde_json::json!({ "lagged": skipped }).to_string(),
+                )),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                // Client frames are ignored apart from close
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => None,
+            },
+        };
+        if let Some(frame) = frame
+            && socket.send(frame).await.is_err()
+        {
+            break;
+        }
+    }
+    tracing::info!("websocket stream closed");
+}
@@ -1,18 +1,186 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+use std::collections::BTreeMap;
+
+// A single reading: number, flag or free text
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MetricValue {
+    Bool(bool),
+    // Kept as a JSON number so integers are forwarded unchanged
+    Number(Number),
+    Text(String),
+}
+
+impl MetricValue {
+    // Numeric view used by alert rules; flags count as 1/0.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => n.as_f64(),
+            Self::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            Self::Text(_) => None,
+        }
+    }
+}
 
 // Incoming payload for HTTP POST /telemetry
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TelemetryIn {
     pub device_id: String,
-    pub temp: Option<f32>,
-    pub pm25: Option<f32>,
-    pub noise: Option<f32>,
+    // Legacy flat fields, still accepted alongside `metrics`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pm25: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noise: Option<Number>,
     pub ts: Option<u64>,
+    // Millisecond timestamp, set when readings are closer together than `ts`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ts_ms: Option<u64>,
+    // Device-side sequence number; with `ts` it identifies a retried reading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, MetricValue>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub units: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    // Any other top-level field is forwarded untouched
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl TelemetryIn {
+    // Deduplication key `(device_id, ts, seq)`, only for readings with a
+    // sequence number.
+    pub fn dedup_key(&self) -> Option<String> {
+        let seq = self.seq?;
+        let ts = match (self.ts_ms, self.ts) {
+            (Some(ms), _) => format!("{ms}ms"),
+            (None, Some(ts)) => ts.to_string(),
+            (None, None) => "-".to_string(),
+        };
+        Some(format!("reading:{}:{ts}:{seq}", self.device_id))
+    }
+
+    // All readings in the body: `metrics`, then the legacy numeric fields for
+    // names not already present in `metrics`. Other top-level fields are
+    // forwarded but are not readings.
+    pub fn resolved_metrics(&self) -> BTreeMap<String, MetricValue> {
+        let mut resolved = self.metrics.clone();
+        let legacy = [
+            ("temp", &self.temp),
+            ("pm25", &self.pm25),
+            ("noise", &self.noise),
+        ];
+        for (name, value) in legacy {
+            if let Some(n) = value {
+                resolved
+                    .entry(name.to_string())
+                    .or_insert_with(|| MetricValue::Number(n.clone()));
+            }
+        }
+        resolved
+    }
+}
+
+// What happened to accepted readings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    // Handed to the connected MQTT client.
+    Published,
+    // Persisted in the outbox until the broker is reachable.
+    Queued,
+    // Acknowledged by the broker (QoS 1 PubAck) before the response.
+    Acknowledged,
+    // Already accepted within the dedup window; not published again.
+    Duplicate,
 }
 
 // Response body for POST /telemetry
 #[derive(Debug, Serialize)]
 pub struct TelemetryResp {
     pub status: &'static str,
+    pub delivery: Delivery,
     pub forwarded_topic: String,
+    // Every topic when a SenML pack spans several devices
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub forwarded_topics: Vec<String>,
+}
+
+// Per-item outcome for POST /telemetry/batch
+#[derive(Debug, Serialize)]
+pub struct BatchItemResult {
+    pub index: usize,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forwarded_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<crate::schema::Violation>,
+}
+
+// Response body for POST /telemetry/batch
+#[derive(Debug, Serialize)]
+pub struct BatchResp {
+    pub accepted: usize,
+    pub rejected: usize,
+    // Valid items already accepted within the dedup window
+    #[serde(skip_serializing_if = "is_zero")]
+    pub duplicates: usize,
+    // Absent when nothing was accepted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivery: Option<Delivery>,
+    pub results: Vec<BatchItemResult>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+// Query string for GET /devices/{device_id}/telemetry
+#[derive(Debug, Deserialize)]
+pub struct TelemetryQuery {
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    pub limit: Option<u32>,
+    // `json` (default) or `senml`.
+    pub format: Option<String>,
+}
+
+// Query string for GET /devices/{device_id}/latest
+#[derive(Debug, Deserialize)]
+pub struct LatestParams {
+    pub format: Option<String>,
+}
+
+// Query string for GET /received
+#[derive(Debug, Deserialize)]
+pub struct ReceivedParams {
+    pub topic: Option<String>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub contains: Option<String>,
+    pub limit: Option<usize>,
+    // Long-poll duration such as `30s`, `500ms` or `2m`.
+    pub wait: Option<String>,
+}
+
+// Query string for GET /alerts
+#[derive(Debug, Deserialize)]
+pub struct AlertParams {
+    pub device_id: Option<String>,
+    // `active` or `resolved`; both when omitted.
+    pub status: Option<String>,
+}
+
+// Query string for GET /invalid
+#[derive(Debug, Deserialize)]
+pub struct InvalidParams {
+    pub limit: Option<u32>,
 }
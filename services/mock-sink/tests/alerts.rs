@@ -0,0 +1,115 @@
+use crate::alerts::{AlertEngine, AlertStatus, Op, Rule, parse_rules};
+use crate::types::MetricValue;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+fn reading(metric: &str, value: f64) -> BTreeMap<String, MetricValue> {
+    BTreeMap::from([(
+        metric.to_string(),
+        MetricValue::Number(serde_json::Number::from_f64(value).unwrap()),
+    )])
+}
+
+fn at(base: DateTime<Utc>, minutes: i64) -> DateTime<Utc> {
+    base + ChronoDuration::minutes(minutes)
+}
+
+#[test]
+fn rules_parse() {
+    let rules =
+        parse_rules("# air quality\nbad-air: pm25 > 35 for 5m clear 30\n\nnoise > 80 # loud\n")
+            .unwrap();
+    assert_eq!(
+        rules[0],
+        Rule {
+            name: "bad-air".into(),
+            metric: "pm25".into(),
+            op: Op::Gt,
+            threshold: 35.0,
+            hold: Duration::from_secs(300),
+            clear: Some(30.0),
+        }
+    );
+    assert_eq!(rules[1].name, "noise > 80");
+    assert_eq!(rules[1].hold, Duration::ZERO);
+
+    assert!(parse_rules("pm25 >").is_err());
+    assert!(parse_rules("pm25 ~ 3").is_err());
+    assert!(parse_rules("pm25 > 3 for ever").is_err());
+    assert!(parse_rules("door == 1 clear 0").is_err());
+}
+
+#[test]
+fn alerts_fire_only_after_the_hold_time() {
+    let engine = AlertEngine::new(parse_rules("pm25 > 35 for 5m").unwrap());
+    let base = Utc::now();
+    assert!(
+        engine
+            .evaluate("a", &reading("pm25", 40.0), base)
+            .is_empty()
+    );
+    assert!(
+        engine
+            .evaluate("a", &reading("pm25", 41.0), at(base, 3))
+            .is_empty()
+    );
+    // A dip restarts the hold time
+    assert!(
+        engine
+            .evaluate("a", &reading("pm25", 20.0), at(base, 4))
+            .is_empty()
+    );
+    assert!(
+        engine
+            .evaluate("a", &reading("pm25", 40.0), at(base, 5))
+            .is_empty()
+    );
+    let fired = engine.evaluate("a", &reading("pm25", 42.0), at(base, 10));
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0].status, AlertStatus::Active);
+    assert_eq!(fired[0].value, 42.0);
+}
+
+#[test]
+fn hysteresis_and_dedup_suppress_oscillation() {
+    let engine = AlertEngine::new(parse_rules("noise > 80 clear 70").unwrap());
+    let base = Utc::now();
+    assert_eq!(engine.evaluate("a", &reading("noise", 85.0), base).len(), 1);
+
+    // Oscillating inside the band neither re-fires nor resolves
+    for (i, value) in [79.0, 81.0, 75.0, 90.0].into_iter().enumerate() {
+        assert!(
+            engine
+                .evaluate("a", &reading("noise", value), at(base, i as i64 + 1))
+                .is_empty()
+        );
+    }
+    let (active, resolved) = engine.alerts();
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].last_value, 90.0);
+    assert!(resolved.is_empty());
+
+    let cleared = engine.evaluate("a", &reading("noise", 65.0), at(base, 10));
+    assert_eq!(cleared.len(), 1);
+    assert_eq!(cleared[0].status, AlertStatus::Resolved);
+    assert_eq!(cleared[0].resolved_at, Some(at(base, 10)));
+
+    let (active, resolved) = engine.alerts();
+    assert!(active.is_empty());
+    assert_eq!(resolved.len(), 1);
+}
+
+#[test]
+fn devices_are_tracked_independently() {
+    let engine = AlertEngine::new(parse_rules("temp < 5").unwrap());
+    let now = Utc::now();
+    assert_eq!(engine.evaluate("a", &reading("temp", 2.0), now).len(), 1);
+    assert_eq!(engine.evaluate("b", &reading("temp", 1.0), now).len(), 1);
+    assert!(
+        engine
+            .evaluate("a", &reading("humidity", 1.0), now)
+            .is_empty()
+    );
+    assert_eq!(engine.alerts().0.len(), 2);
+}
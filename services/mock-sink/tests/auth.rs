@@ -0,0 +1,103 @@
+use crate::auth::{AuthContext, TokenValidateResponse, authenticate_device, ensure_same_device};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::{Json, Router, routing::post};
+use reqwest::Client;
+use std::net::SocketAddr;
+use tokio::{task::JoinHandle, time::Duration};
+
+async fn spawn_validate_server(response: TokenValidateResponse) -> (AuthContext, JoinHandle<()>) {
+    let router = Router::new().route(
+        "/auth/token/validate",
+        post(move |Json::<serde_json::Value>(_)| {
+            let response = response.clone();
+            async move { (StatusCode::OK, Json(response)) }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let handle = tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+
+    let auth = AuthContext {
+        client: Client::builder().build().unwrap(),
+        validate_url: format!("http://{addr}/auth/token/validate"),
+    };
+
+    // ensure server is ready
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    (auth, handle)
+}
+
+fn bearer(token: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        format!("Bearer {token}").parse().unwrap(),
+    );
+    headers
+}
+
+fn device_token(device_id: &str) -> TokenValidateResponse {
+    TokenValidateResponse {
+        valid: true,
+        service: None,
+        device_id: Some(device_id.into()),
+    }
+}
+
+#[tokio::test]
+async fn device_auth_missing_header() {
+    let (auth, handle) = spawn_validate_server(device_token("dev-1")).await;
+    let result = authenticate_device(&auth, &HeaderMap::new()).await;
+    handle.abort();
+    assert!(matches!(result, Err((StatusCode::UNAUTHORIZED, _))));
+}
+
+#[tokio::test]
+async fn device_auth_invalid_token() {
+    let (auth, handle) = spawn_validate_server(TokenValidateResponse {
+        valid: false,
+        service: None,
+        device_id: None,
+    })
+    .await;
+    let result = authenticate_device(&auth, &bearer("bad-token")).await;
+    handle.abort();
+    assert!(matches!(result, Err((StatusCode::UNAUTHORIZED, _))));
+}
+
+#[tokio::test]
+async fn device_auth_rejects_other_device() {
+    let (auth, handle) = spawn_validate_server(device_token("dev-2")).await;
+    let token_device = authenticate_device(&auth, &bearer("good-token"))
+        .await
+        .unwrap();
+    handle.abort();
+    let result = ensure_same_device(&token_device, "dev-1");
+    assert!(matches!(result, Err((StatusCode::FORBIDDEN, _))));
+}
+
+#[tokio::test]
+async fn device_auth_rejects_service_token() {
+    let (auth, handle) = spawn_validate_server(TokenValidateResponse {
+        valid: true,
+        service: Some("mock-ota".into()),
+        device_id: None,
+    })
+    .await;
+    let result = authenticate_device(&auth, &bearer("service-token")).await;
+    handle.abort();
+    assert!(matches!(result, Err((StatusCode::FORBIDDEN, _))));
+}
+
+#[tokio::test]
+async fn device_auth_success() {
+    let (auth, handle) = spawn_validate_server(device_token("dev-1")).await;
+    let result = authenticate_device(&auth, &bearer("good-token")).await;
+    handle.abort();
+    assert_eq!(result.unwrap(), "dev-1");
+    assert!(ensure_same_device("dev-1", "dev-1").is_ok());
+}
@@ -0,0 +1,174 @@
+use crate::codec::PayloadFormat;
+use crate::handlers::{parse_batch, telemetry};
+use crate::schema::SchemaRegistry;
+use crate::store::{Retention, Store};
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+};
+use serde_json::json;
+
+fn cbor(value: &serde_json::Value) -> Vec<u8> {
+    PayloadFormat::Cbor.encode(value).unwrap()
+}
+
+fn headers(content_type: &'static str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers
+}
+
+#[test]
+fn formats_are_negotiated() {
+    assert_eq!(
+        PayloadFormat::from_content_type("application/cbor"),
+        Some(PayloadFormat::Cbor)
+    );
+    assert_eq!(
+        PayloadFormat::from_content_type("application/msgpack; charset=binary"),
+        Some(PayloadFormat::MsgPack)
+    );
+    assert_eq!(PayloadFormat::from_content_type("text/plain"), None);
+    assert_eq!(
+        PayloadFormat::from_topic("argus/devices/a/cbor"),
+        PayloadFormat::Cbor
+    );
+    assert_eq!(
+        PayloadFormat::from_topic("argus/devices/a"),
+        PayloadFormat::Json
+    );
+    assert_eq!(
+        PayloadFormat::resolve("argus/devices/a/cbor", Some("application/msgpack")),
+        PayloadFormat::MsgPack
+    );
+}
+
+#[test]
+fn binary_payloads_decode_to_json() {
+    let reading = json!({"device_id": "a", "metrics": {"pm25": 12, "temp": 21.5, "ok": true}});
+    for format in [PayloadFormat::Cbor, PayloadFormat::MsgPack] {
+        let encoded = format.encode(&reading).unwrap();
+        assert_eq!(format.decode(&encoded).unwrap(), reading);
+    }
+
+    // Integer map keys (e.g. SenML labels) and byte strings stay representable
+    let mut raw = Vec::new();
+    let value = ciborium::Value::Map(vec![
+        (
+            ciborium::Value::Integer(2.into()),
+            ciborium::Value::Text("temp".into()),
+        ),
+        (
+            ciborium::Value::Text("raw".into()),
+            ciborium::Value::Bytes(vec![0xca, 0xfe]),
+        ),
+    ]);
+    ciborium::into_writer(&value, &mut raw).unwrap();
+    assert_eq!(
+        PayloadFormat::Cbor.decode(&raw).unwrap(),
+        json!({"2": "temp", "raw": "cafe"})
+    );
+}
+
+#[tokio::test]
+async fn telemetry_accepts_cbor_and_forwards_on_suffixed_topic() {
+    let state = super::test_state(SchemaRegistry::default());
+    let body = cbor(&json!({"device_id": "a", "metrics": {"co2": 410}}));
+    let Json(resp) = telemetry(State(state), headers("application/cbor"), Bytes::from(body))
+        .await
+        .unwrap();
+    assert_eq!(resp.forwarded_topic, "argus/devices/a/cbor");
+}
+
+#[tokio::test]
+async fn topic_schemas_apply_to_binary_posts() {
+    let mut schemas = SchemaRegistry::default();
+    schemas
+        .add(
+            "telemetry",
+            json!({
+                "x-argus-topic": "argus/devices/+",
+                "type": "object",
+                "required": ["metrics"]
+            }),
+        )
+        .unwrap();
+    let state = super::test_state(schemas);
+
+    let err = telemetry(
+        State(state.clone()),
+        headers("application/cbor"),
+        Bytes::from(cbor(&json!({"device_id": "a"}))),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(err.violations[0].schema, "telemetry");
+
+    let body = cbor(&json!({"device_id": "a", "metrics": {"co2": 410}}));
+    let Json(resp) = telemetry(State(state), headers("application/cbor"), Bytes::from(body))
+        .await
+        .unwrap();
+    assert_eq!(resp.forwarded_topic, "argus/devices/a/cbor");
+    assert_eq!(
+        PayloadFormat::canonical_topic("argus/devices/a/cbor"),
+        "argus/devices/a"
+    );
+}
+
+#[tokio::test]
+async fn telemetry_rejects_unsupported_and_malformed_bodies() {
+    let state = super::test_state(SchemaRegistry::default());
+    let err = telemetry(
+        State(state.clone()),
+        headers("text/plain"),
+        Bytes::from_static(b"hi"),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let err = telemetry(
+        State(state),
+        headers("application/msgpack"),
+        Bytes::from_static(&[0x81, 0xa1]),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn batches_accept_binary_arrays() {
+    let body = cbor(&json!([{"device_id": "a"}, {"device_id": "b"}]));
+    let items = parse_batch("application/cbor", &body).unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].as_ref().unwrap()["device_id"], "b");
+}
+
+#[tokio::test]
+async fn stored_binary_messages_show_decoded_payload() {
+    let store = Store::open_in_memory(Retention::default()).unwrap();
+    let reading = json!({"device_id": "a", "pm25": 9});
+    let encoded = PayloadFormat::MsgPack.encode(&reading).unwrap();
+    store
+        .insert(
+            &super::consumed("argus/devices/a/msgpack", &encoded),
+            Some("a"),
+            PayloadFormat::MsgPack,
+            chrono::Utc::now(),
+            vec![],
+        )
+        .await
+        .unwrap();
+
+    let latest = store
+        .latest("a", vec!["argus/devices/a/msgpack".into()])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.payload, reading);
+    assert_eq!(latest.encoding.as_deref(), Some("msgpack"));
+}
@@ -0,0 +1,145 @@
+use crate::commands::{Command, CommandStatus, CommandTracker};
+use chrono::Utc;
+use serde_json::{Value, json};
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+fn tracked(tracker: &CommandTracker, device_id: &str) -> Uuid {
+    let now = Utc::now();
+    let command = Command {
+        correlation_id: Uuid::new_v4(),
+        device_id: device_id.into(),
+        command: "reboot".into(),
+        params: Value::Null,
+        status: CommandStatus::Pending,
+        created_at: now,
+        expires_at: now + chrono::Duration::seconds(60),
+        acked_at: None,
+        completed_at: None,
+        result: None,
+        message: None,
+    };
+    let id = command.correlation_id;
+    tracker.insert(command);
+    id
+}
+
+#[test]
+fn replies_move_commands_through_their_states() {
+    let tracker = CommandTracker::new(Duration::from_secs(60), None);
+    let id = tracked(&tracker, "a");
+
+    let acked = tracker
+        .handle_reply("a", &json!({"correlation_id": id, "status": "ack"}), None)
+        .unwrap();
+    assert_eq!(acked.status, CommandStatus::Acked);
+    assert!(acked.acked_at.is_some());
+
+    let done = tracker
+        .handle_reply(
+            "a",
+            &json!({"correlation_id": id, "status": "success", "result": {"uptime": 3}}),
+            None,
+        )
+        .unwrap();
+    assert_eq!(done.status, CommandStatus::Succeeded);
+    assert_eq!(done.result, Some(json!({"uptime": 3})));
+
+    // Terminal commands ignore late replies and never time out
+    let late = tracker
+        .handle_reply(
+            "a",
+            &json!({"correlation_id": id, "status": "failed"}),
+            None,
+        )
+        .unwrap();
+    assert_eq!(late.status, CommandStatus::Succeeded);
+    assert!(!tracker.expire(id, Utc::now()));
+}
+
+#[test]
+fn replies_fall_back_to_mqtt_correlation_data() {
+    let tracker = CommandTracker::new(Duration::from_secs(60), None);
+    let id = tracked(&tracker, "a");
+    let correlation = id.to_string();
+
+    let done = tracker
+        .handle_reply("a", &json!({"status": "ok"}), Some(correlation.as_bytes()))
+        .unwrap();
+    assert_eq!(done.correlation_id, id);
+    assert_eq!(done.status, CommandStatus::Succeeded);
+    assert!(
+        tracker
+            .handle_reply("a", &json!({"status": "ok"}), None)
+            .is_none()
+    );
+    assert!(
+        tracker
+            .handle_reply("a", &json!({"status": "ok"}), Some(b"not-a-uuid"))
+            .is_none()
+    );
+}
+
+#[test]
+fn replies_from_other_devices_or_unknown_statuses_are_ignored() {
+    let tracker = CommandTracker::new(Duration::from_secs(60), None);
+    let id = tracked(&tracker, "a");
+    let other = tracker
+        .handle_reply("b", &json!({"correlation_id": id, "status": "ok"}), None)
+        .unwrap();
+    assert_eq!(other.status, CommandStatus::Pending);
+    assert!(
+        tracker
+            .handle_reply("a", &json!({"correlation_id": id, "status": "maybe"}), None)
+            .is_none()
+    );
+    assert!(tracker.handle_reply("a", &json!("ok"), None).is_none());
+}
+
+#[test]
+fn unanswered_commands_time_out() {
+    let tracker = CommandTracker::new(Duration::from_secs(60), None);
+    let id = tracked(&tracker, "a");
+    assert!(tracker.expire(id, Utc::now()));
+    assert_eq!(tracker.get(id).unwrap().status, CommandStatus::TimedOut);
+    assert_eq!(tracker.list("a").len(), 1);
+    assert!(tracker.list("b").is_empty());
+}
+
+#[test]
+fn finished_commands_are_pruned_after_the_retention_window() {
+    let tracker = CommandTracker::new(Duration::from_secs(60), Some(Duration::from_secs(600)));
+    let finished = tracked(&tracker, "a");
+    let pending = tracked(&tracker, "a");
+    let now = Utc::now();
+    assert!(tracker.expire(finished, now));
+
+    assert_eq!(tracker.prune(now + chrono::Duration::seconds(599)), 0);
+    assert_eq!(tracker.prune(now + chrono::Duration::seconds(600)), 1);
+    assert!(tracker.get(finished).is_none());
+    // unfinished commands are kept however old they are
+    assert_eq!(tracker.prune(now + chrono::Duration::days(1)), 0);
+    assert!(tracker.get(pending).is_some());
+}
+
+#[tokio::test]
+async fn wait_returns_once_the_command_finishes() {
+    let tracker = Arc::new(CommandTracker::new(Duration::from_secs(60), None));
+    let id = tracked(&tracker, "a");
+
+    let pending = tracker.wait(id, Duration::from_millis(20)).await.unwrap();
+    assert_eq!(pending.status, CommandStatus::Pending);
+
+    let replier = Arc::clone(&tracker);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        replier.handle_reply(
+            "a",
+            &json!({"correlation_id": id, "status": "error", "message": "busy"}),
+            None,
+        );
+    });
+    let done = tracker.wait(id, Duration::from_secs(5)).await.unwrap();
+    assert_eq!(done.status, CommandStatus::Failed);
+    assert_eq!(done.message.as_deref(), Some("busy"));
+}
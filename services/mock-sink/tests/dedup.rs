@@ -0,0 +1,123 @@
+use crate::codec::PayloadFormat;
+use crate::dedup::{Deduplicator, consumed_key};
+use crate::handlers::{telemetry, telemetry_batch};
+use crate::schema::SchemaRegistry;
+use crate::types::Delivery;
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, header},
+};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use topic_layout::TopicLayout;
+
+#[test]
+fn keys_expire_after_the_window() {
+    let dedup = Deduplicator::new(Some(Duration::from_secs(10)));
+    let start = Instant::now();
+    assert!(dedup.claim("k", start));
+    assert!(!dedup.claim("k", start + Duration::from_secs(10)));
+    assert!(dedup.claim("k", start + Duration::from_secs(11)));
+
+    dedup.release("k");
+    assert!(dedup.claim("k", start + Duration::from_secs(12)));
+
+    let disabled = Deduplicator::new(None);
+    assert!(disabled.claim("k", start));
+    assert!(disabled.claim("k", start));
+}
+
+#[test]
+fn consumed_readings_are_keyed_by_ts_and_seq() {
+    let payload = json!({"device_id": "a", "ts": 1700000000, "seq": 7, "metrics": {"co2": 410}});
+    let payload = payload.to_string().into_bytes();
+    let topics = TopicLayout::with_prefix("argus/devices/");
+    let key = |topic: &str| consumed_key(&topics, "a", topic, PayloadFormat::Json, &payload);
+    assert_eq!(
+        key("argus/devices/a").as_deref(),
+        Some("reading:a:1700000000:7")
+    );
+    assert_eq!(key("argus/devices/a/status"), None);
+
+    let unsequenced = br#"{"device_id": "a", "ts": 1700000000}"#;
+    assert_eq!(
+        consumed_key(
+            &topics,
+            "a",
+            "argus/devices/a",
+            PayloadFormat::Json,
+            unsequenced
+        ),
+        None
+    );
+}
+
+fn json_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    headers
+}
+
+#[tokio::test]
+async fn retried_telemetry_is_acknowledged_once() {
+    let state = super::test_state(SchemaRegistry::default());
+    let body = Bytes::from(json!({"device_id": "a", "ts": 1700000000, "seq": 7}).to_string());
+    let Json(first) = telemetry(State(state.clone()), json_headers(), body.clone())
+        .await
+        .unwrap();
+    assert_eq!(first.delivery, Delivery::Queued);
+    let Json(retry) = telemetry(State(state.clone()), json_headers(), body)
+        .await
+        .unwrap();
+    assert_eq!(retry.delivery, Delivery::Duplicate);
+    assert_eq!(retry.forwarded_topic, "argus/devices/a");
+    assert_eq!(state.outbox.depth(), 1);
+
+    // The Idempotency-Key covers the request, whatever it contains
+    let mut headers = json_headers();
+    headers.insert("idempotency-key", "req-1".parse().unwrap());
+    for (device, expected) in [("b", Delivery::Queued), ("c", Delivery::Duplicate)] {
+        let body = Bytes::from(json!({"device_id": device}).to_string());
+        let Json(resp) = telemetry(State(state.clone()), headers.clone(), body)
+            .await
+            .unwrap();
+        assert_eq!(resp.delivery, expected);
+    }
+    assert_eq!(state.outbox.depth(), 2);
+}
+
+#[tokio::test]
+async fn batch_marks_duplicate_items() {
+    let state = super::test_state(SchemaRegistry::default());
+    let body = json!([
+        {"device_id": "a", "ts": 1, "seq": 1},
+        {"device_id": "a", "ts": 2, "seq": 2},
+    ]);
+    let Json(first) = telemetry_batch(
+        State(state.clone()),
+        json_headers(),
+        Bytes::from(body.to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(first.accepted, 2);
+
+    let body = json!([
+        {"device_id": "a", "ts": 2, "seq": 2},
+        {"device_id": "a", "ts": 3, "seq": 3},
+        {"device_id": 5},
+    ]);
+    let Json(resp) = telemetry_batch(
+        State(state.clone()),
+        json_headers(),
+        Bytes::from(body.to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!((resp.accepted, resp.rejected, resp.duplicates), (1, 1, 1));
+    assert_eq!(resp.results[0].status, "duplicate");
+    assert_eq!(resp.results[1].status, "ok");
+    assert_eq!(state.outbox.depth(), 3);
+}
@@ -0,0 +1,184 @@
+use crate::codec::PayloadFormat;
+use crate::handlers::{
+    AppState, health, parse_batch, parse_wait, received, telemetry, telemetry_batch,
+};
+use crate::schema::SchemaRegistry;
+use crate::types::ReceivedParams;
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+};
+use serde_json::json;
+use std::time::Duration;
+use topic_layout::{Templates, TopicLayout};
+
+#[test]
+fn wait_durations_parse() {
+    assert_eq!(parse_wait("30s"), Some(Duration::from_secs(30)));
+    assert_eq!(parse_wait("500ms"), Some(Duration::from_millis(500)));
+    assert_eq!(parse_wait("2m"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_wait("15"), Some(Duration::from_secs(15)));
+    assert_eq!(parse_wait("soon"), None);
+    assert_eq!(parse_wait("10h"), None);
+}
+
+#[test]
+fn batch_accepts_json_arrays() {
+    let items = parse_batch(
+        "application/json",
+        br#"[{"device_id":"a"},{"device_id":"b"}]"#,
+    )
+    .unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].as_ref().unwrap()["device_id"], "b");
+
+    let err = parse_batch("application/json", br#"{"device_id":"a"}"#).unwrap_err();
+    assert_eq!(err.status, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn batch_ndjson_reports_bad_lines_individually() {
+    let body = b"{\"device_id\":\"a\"}\n\nnot json\n{\"device_id\":\"c\"}\n";
+    let items = parse_batch("application/x-ndjson; charset=utf-8", body).unwrap();
+    assert_eq!(items.len(), 3);
+    assert!(items[0].is_ok());
+    assert_eq!(
+        items[1].as_ref().unwrap_err().status,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(items[2].as_ref().unwrap()["device_id"], "c");
+}
+
+#[tokio::test]
+async fn batch_reports_partial_failures() {
+    let mut schemas = SchemaRegistry::default();
+    schemas
+        .add(
+            "env-sensor",
+            json!({"properties": {"metrics": {"properties": {"co2": {"minimum": 0}}}}}),
+        )
+        .unwrap();
+    let state = super::test_state(schemas);
+    let body = json!([
+        {"device_id": "a", "metrics": {"co2": 400}},
+        {"device_id": "b", "device_type": "env-sensor", "metrics": {"co2": -5}},
+        {"temp": 21.0}
+    ]);
+    let Json(resp) = telemetry_batch(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(body.to_string()),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!((resp.accepted, resp.rejected), (1, 2));
+    assert_eq!(
+        resp.results[0].forwarded_topic.as_deref(),
+        Some("argus/devices/a")
+    );
+    assert_eq!(resp.results[1].code, Some(422));
+    assert_eq!(resp.results[1].violations.len(), 1);
+    assert_eq!(resp.results[2].code, Some(422));
+}
+
+#[tokio::test]
+async fn batch_rejects_too_many_items() {
+    let state = super::test_state(SchemaRegistry::default());
+    let body =
+        json!([{"device_id": "a"}, {"device_id": "a"}, {"device_id": "a"}, {"device_id": "a"}]);
+    let err = telemetry_batch(
+        State(state),
+        HeaderMap::new(),
+        Bytes::from(body.to_string()),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn telemetry_topic_follows_the_layout_template() {
+    let state = std::sync::Arc::new(AppState {
+        topics: TopicLayout::new(
+            "argus/devices/",
+            Templates {
+                telemetry: Some("{tenant}/{device_id}/telemetry/{channel}".into()),
+                vars: Some("tenant=acme".into()),
+                ..Templates::default()
+            },
+        )
+        .unwrap(),
+        ..(*super::test_state(SchemaRegistry::default())).clone()
+    });
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+
+    let body = json!({"device_id": "a", "tags": {"channel": "env"}, "metrics": {"co2": 410}});
+    let Json(resp) = telemetry(
+        State(state.clone()),
+        headers.clone(),
+        Bytes::from(body.to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(resp.forwarded_topic, "acme/a/telemetry/env");
+
+    let body = json!({"device_id": "a", "metrics": {"co2": 410}});
+    let err = telemetry(State(state), headers, Bytes::from(body.to_string()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(err.error, "topic variable 'channel' is not set");
+}
+
+#[tokio::test]
+async fn waiting_for_received_ignores_earlier_messages() {
+    let state = super::test_state(SchemaRegistry::default());
+    state
+        .store
+        .insert(
+            &super::consumed("argus/devices/a", b"{}"),
+            Some("a"),
+            PayloadFormat::Json,
+            chrono::Utc::now() - chrono::Duration::seconds(5),
+            vec![],
+        )
+        .await
+        .unwrap();
+    let params = |wait: Option<&str>| ReceivedParams {
+        topic: Some("argus/devices/+".into()),
+        since: None,
+        contains: None,
+        limit: None,
+        wait: wait.map(Into::into),
+    };
+
+    let Json(found) = received(State(state.clone()), Query(params(None)))
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    let err = received(State(state), Query(params(Some("50ms"))))
+        .await
+        .unwrap_err();
+    assert_eq!(err.0, StatusCode::REQUEST_TIMEOUT);
+}
+
+#[tokio::test]
+async fn health_reports_broker_connection() {
+    let state = super::test_state(SchemaRegistry::default());
+    state.connection.on_error(&"connection refused");
+    let Json(body) = health(State(state.clone())).await;
+    assert_eq!(body["mqtt"]["connected"], false);
+    assert_eq!(body["mqtt"]["failures"], 1);
+    assert_eq!(body["mqtt"]["last_error"], "connection refused");
+    assert!(body["mqtt"]["connected_since"].is_null());
+
+    state.connection.on_connack();
+    let Json(body) = health(State(state)).await;
+    assert_eq!(body["mqtt"]["connected"], true);
+    assert_eq!(body["mqtt"]["failures"], 0);
+    assert!(body["mqtt"]["connected_since"].is_string());
+}
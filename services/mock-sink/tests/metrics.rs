@@ -0,0 +1,90 @@
+use crate::metrics::{DeviceGauges, render, track};
+use crate::store::StoredMessage;
+use axum::{Router, body::to_bytes, routing::get};
+use chrono::{Duration as ChronoDuration, Utc};
+use prometheus::{Encoder, TextEncoder};
+use serde_json::{Value, json};
+use std::time::Duration;
+use topic_layout::TopicLayout;
+
+#[tokio::test]
+async fn requests_are_counted_by_matched_route() {
+    let router = Router::new()
+        .route("/things/:id", get(|| async { "ok" }))
+        .layer(axum::middleware::from_fn(track));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    let client = reqwest::Client::new();
+    for path in ["/things/1", "/things/2", "/missing"] {
+        client
+            .get(format!("http://{addr}{path}"))
+            .send()
+            .await
+            .unwrap();
+    }
+
+    let body = to_bytes(render().await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        text.contains(r#"http_requests_total{method="GET",route="/things/:id",status="200"} 2"#)
+    );
+    assert!(text.contains(r#"route="unmatched",status="404""#));
+    assert!(text.contains("http_request_duration_seconds_bucket"));
+}
+
+fn telemetry(device_id: &str, payload: Value, at: chrono::DateTime<Utc>) -> StoredMessage {
+    StoredMessage {
+        id: 1,
+        topic: format!("argus/devices/{device_id}"),
+        device_id: Some(device_id.into()),
+        payload,
+        encoding: None,
+        received_at: at,
+        violations: Vec::new(),
+        properties: None,
+    }
+}
+
+#[test]
+fn device_gauges_export_latest_values_until_stale() {
+    let gauges = DeviceGauges::new(
+        TopicLayout::with_prefix("argus/devices/"),
+        Some(Duration::from_secs(300)),
+    );
+    let base = Utc::now();
+    gauges.observe(&telemetry(
+        "a",
+        json!({"device_id": "a", "metrics": {"pm25": 12, "door": true, "fw": "1.2"}}),
+        base,
+    ));
+    gauges.observe(&telemetry(
+        "a",
+        json!({"device_id": "a", "pm25": 14.5}),
+        base,
+    ));
+    gauges.observe(&telemetry(
+        "b",
+        json!({"device_id": "b", "metrics": {"temp": 21}}),
+        base + ChronoDuration::minutes(4),
+    ));
+
+    let families = gauges.gather(base + ChronoDuration::minutes(1)).unwrap();
+    let mut body = Vec::new();
+    TextEncoder::new().encode(&families, &mut body).unwrap();
+    let text = String::from_utf8(body).unwrap();
+    assert!(text.contains(r#"argus_telemetry{device_id="a",metric="pm25"} 14.5"#));
+    assert!(text.contains(r#"argus_telemetry{device_id="a",metric="door"} 1"#));
+    assert!(!text.contains(r#"metric="fw""#));
+
+    // Device a has been silent for six minutes; only b is still exported
+    let families = gauges.gather(base + ChronoDuration::minutes(6)).unwrap();
+    let mut body = Vec::new();
+    TextEncoder::new().encode(&families, &mut body).unwrap();
+    let text = String::from_utf8(body).unwrap();
+    assert!(!text.contains(r#"device_id="a""#));
+    assert!(text.contains(r#"argus_telemetry{device_id="b",metric="temp"} 21"#));
+}
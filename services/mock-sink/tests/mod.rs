@@ -0,0 +1,77 @@
+mod alerts;
+mod auth;
+mod codec;
+mod commands;
+mod dedup;
+mod handlers;
+mod metrics;
+mod outbox;
+mod overload;
+mod presence;
+mod publisher;
+mod schema;
+mod senml;
+mod shadow;
+mod store;
+mod stream;
+mod topic;
+mod types;
+
+use crate::commands::CommandTracker;
+use crate::dedup::Deduplicator;
+use crate::handlers::AppState;
+use crate::metrics::DeviceGauges;
+use crate::outbox::Outbox;
+use crate::overload::Limiter;
+use crate::presence::PresenceTracker;
+use crate::publisher::Publisher;
+use crate::schema::SchemaRegistry;
+use crate::store::{Retention, Store};
+use axum::body::Bytes;
+use mqtt_client::{Client, ConnectionState, Message, Properties};
+use rumqttc::{AsyncClient, MqttOptions};
+use std::{sync::Arc, time::Duration};
+use topic_layout::TopicLayout;
+
+/// A consumed message without MQTT v5 properties.
+fn consumed(topic: &str, payload: &[u8]) -> Message {
+    Message {
+        topic: topic.to_string(),
+        payload: Bytes::copy_from_slice(payload),
+        properties: Properties::default(),
+    }
+}
+
+/// Anonymous-mode state backed by an in-memory store; there is no MQTT event
+/// loop, so background publishes fail and are only logged.
+fn test_state(schemas: SchemaRegistry) -> Arc<AppState> {
+    let (mqtt, _) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 1024);
+    let (events, _) = tokio::sync::broadcast::channel(16);
+    let connection = ConnectionState::default();
+    Arc::new(AppState {
+        mqtt: Publisher::new(Client::V4(mqtt)),
+        topics: TopicLayout::with_prefix("argus/devices/"),
+        auth: None,
+        store: Store::open_in_memory(Retention::default()).unwrap(),
+        events,
+        schemas: Arc::new(schemas),
+        batch_max_items: 3,
+        alerts: Arc::default(),
+        presence: Arc::new(PresenceTracker::new(
+            TopicLayout::with_prefix("argus/devices/"),
+            None,
+        )),
+        commands: Arc::new(CommandTracker::new(Duration::from_secs(60), None)),
+        shadows: Arc::default(),
+        device_gauges: Arc::new(DeviceGauges::new(
+            TopicLayout::with_prefix("argus/devices/"),
+            None,
+        )),
+        outbox: Arc::new(Outbox::open_in_memory(connection.clone()).unwrap()),
+        publish_confirm: false,
+        publish_confirm_timeout: Duration::from_secs(5),
+        limiter: Arc::new(Limiter::new(16, None)),
+        dedup: Arc::new(Deduplicator::new(Some(Duration::from_secs(60)))),
+        connection,
+    })
+}
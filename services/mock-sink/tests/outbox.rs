@@ -0,0 +1,96 @@
+use crate::handlers::telemetry;
+use crate::outbox::Outbox;
+use crate::publisher::Publisher;
+use crate::schema::SchemaRegistry;
+use crate::types::Delivery;
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, header},
+};
+use mqtt_client::{Client, ConnectionState};
+use rumqttc::{AsyncClient, MqttOptions};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+fn items(topics: &[&str]) -> Vec<(String, Vec<u8>)> {
+    topics
+        .iter()
+        .map(|t| (t.to_string(), t.as_bytes().to_vec()))
+        .collect()
+}
+
+#[tokio::test]
+async fn queued_readings_survive_a_restart_in_order() {
+    let path = std::env::temp_dir().join(format!("mock-sink-outbox-{}.db", uuid::Uuid::new_v4()));
+    {
+        let outbox = Outbox::open(&path, ConnectionState::default()).unwrap();
+        outbox.enqueue(items(&["t/1", "t/2"])).await.unwrap();
+        outbox.enqueue(items(&["t/3"])).await.unwrap();
+        let first = outbox.peek(1).await.unwrap().remove(0);
+        outbox.remove(first.id).await.unwrap();
+    }
+
+    let outbox = Outbox::open(&path, ConnectionState::default()).unwrap();
+    assert_eq!(outbox.depth(), 2);
+    let topics: Vec<String> = outbox
+        .peek(10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|q| q.topic)
+        .collect();
+    assert_eq!(topics, ["t/2", "t/3"]);
+    drop(outbox);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn drain_removes_readings_once_acknowledged() {
+    // Keep the event loop alive so publishes are accepted by the client
+    let (client, _eventloop) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 16);
+    let mqtt = Publisher::new(Client::V4(client));
+    let connection = ConnectionState::default();
+    let outbox = Arc::new(Outbox::open_in_memory(connection.clone()).unwrap());
+    outbox.enqueue(items(&["t/1", "t/2", "t/3"])).await.unwrap();
+    let ack_timeout = Duration::from_millis(100);
+
+    assert_eq!(outbox.drain(&mqtt, ack_timeout).await.unwrap(), 0);
+    assert_eq!(outbox.depth(), 3);
+
+    // Without a PubAck every reading stays queued
+    connection.on_connack();
+    assert!(outbox.drain(&mqtt, ack_timeout).await.is_err());
+    assert_eq!(outbox.depth(), 3);
+
+    let draining = {
+        let (outbox, mqtt) = (Arc::clone(&outbox), mqtt.clone());
+        tokio::spawn(async move { outbox.drain(&mqtt, Duration::from_secs(5)).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // Packet ids 1-3 belong to the unacknowledged first attempt
+    for pkid in 1..=6 {
+        mqtt.on_outgoing_publish(pkid);
+        mqtt.on_puback(pkid);
+    }
+    assert_eq!(draining.await.unwrap().unwrap(), 3);
+    assert_eq!(outbox.depth(), 0);
+}
+
+#[tokio::test]
+async fn telemetry_is_queued_while_disconnected() {
+    let state = super::test_state(SchemaRegistry::default());
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    let body = json!({"device_id": "a", "metrics": {"co2": 410}});
+    let Json(resp) = telemetry(State(state.clone()), headers, Bytes::from(body.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(resp.delivery, Delivery::Queued);
+    assert_eq!(state.outbox.depth(), 1);
+    assert_eq!(
+        state.outbox.peek(1).await.unwrap()[0].topic,
+        "argus/devices/a"
+    );
+}
@@ -0,0 +1,80 @@
+use crate::handlers::{AppState, telemetry};
+use crate::overload::Limiter;
+use crate::schema::SchemaRegistry;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+#[tokio::test]
+async fn limiter_counts_in_flight_requests() {
+    let limiter = Limiter::new(2, None);
+    let first = limiter.acquire().await.unwrap();
+    let _second = limiter.acquire().await.unwrap();
+    assert_eq!(limiter.in_flight(), 2);
+    assert!(limiter.acquire().await.is_none());
+
+    drop(first);
+    assert_eq!(limiter.in_flight(), 1);
+    assert!(limiter.acquire().await.is_some());
+}
+
+#[tokio::test]
+async fn limiter_waits_up_to_the_deadline() {
+    let limiter = Arc::new(Limiter::new(1, Some(Duration::from_millis(200))));
+    let held = limiter.acquire().await.unwrap();
+    let waiting = {
+        let limiter = Arc::clone(&limiter);
+        tokio::spawn(async move { limiter.acquire().await.is_some() })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(held);
+    assert!(waiting.await.unwrap());
+
+    let _held = limiter.acquire().await.unwrap();
+    let start = std::time::Instant::now();
+    assert!(limiter.acquire().await.is_none());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn overloaded_telemetry_is_rejected_with_retry_after() {
+    let state = Arc::new(AppState {
+        limiter: Arc::new(Limiter::new(1, None)),
+        ..(*super::test_state(SchemaRegistry::default())).clone()
+    });
+    let _busy = state.limiter.acquire().await.unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    let body = json!({"device_id": "a", "metrics": {"co2": 410}});
+    let response = telemetry(State(state.clone()), headers, Bytes::from(body.to_string()))
+        .await
+        .unwrap_err()
+        .into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    assert_eq!(state.outbox.depth(), 0);
+}
+
+#[tokio::test]
+async fn invalid_telemetry_is_rejected_before_taking_a_slot() {
+    let state = Arc::new(AppState {
+        limiter: Arc::new(Limiter::new(1, None)),
+        ..(*super::test_state(SchemaRegistry::default())).clone()
+    });
+    let _busy = state.limiter.acquire().await.unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    let response = telemetry(State(state.clone()), headers, Bytes::from_static(b"{}"))
+        .await
+        .unwrap_err()
+        .into_response();
+    assert_ne!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.status().is_client_error());
+}
@@ -0,0 +1,128 @@
+use crate::presence::{PresenceSource, PresenceState, PresenceTracker};
+use crate::store::StoredMessage;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde_json::{Value, json};
+use std::time::Duration;
+use topic_layout::{Templates, TopicLayout};
+
+fn message(topic: &str, payload: Value, at: DateTime<Utc>) -> StoredMessage {
+    StoredMessage {
+        id: 1,
+        topic: topic.into(),
+        device_id: Some("a".into()),
+        payload,
+        encoding: None,
+        received_at: at,
+        violations: Vec::new(),
+        properties: None,
+    }
+}
+
+#[test]
+fn status_and_telemetry_drive_presence() {
+    let tracker = PresenceTracker::new(TopicLayout::with_prefix("argus/devices/"), None);
+    let base = Utc::now();
+
+    let event = tracker
+        .observe(&message("argus/devices/a/status", json!("online"), base))
+        .unwrap();
+    assert_eq!(
+        (event.state, event.source),
+        (PresenceState::Online, PresenceSource::Status)
+    );
+    // Heartbeats and readings while online are not transitions
+    assert!(
+        tracker
+            .observe(&message("argus/devices/a", json!({"pm25": 3}), base))
+            .is_none()
+    );
+
+    let lwt = json!({"state": "offline", "reason": "lwt"});
+    let event = tracker
+        .observe(&message("argus/devices/a/status", lwt, base))
+        .unwrap();
+    assert_eq!(
+        (event.state, event.source),
+        (PresenceState::Offline, PresenceSource::Lwt)
+    );
+
+    let event = tracker
+        .observe(&message("argus/devices/a/cbor", json!({}), base))
+        .unwrap();
+    assert_eq!(
+        (event.state, event.source),
+        (PresenceState::Online, PresenceSource::Telemetry)
+    );
+
+    let presence = tracker.get("a").unwrap();
+    assert_eq!(presence.state, PresenceState::Online);
+    assert_eq!(presence.history.len(), 3);
+}
+
+#[test]
+fn status_template_is_followed() {
+    let topics = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            telemetry: Some("{tenant}/{device_id}/telemetry".into()),
+            status: Some("{tenant}/{device_id}/status".into()),
+            vars: Some("tenant=acme".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap();
+    let tracker = PresenceTracker::new(topics, None);
+    let base = Utc::now();
+
+    let event = tracker
+        .observe(&message("acme/a/status", json!("offline"), base))
+        .unwrap();
+    assert_eq!(
+        (event.state, event.source),
+        (PresenceState::Offline, PresenceSource::Status)
+    );
+    assert!(
+        tracker
+            .observe(&message("argus/devices/a/status", json!("online"), base))
+            .is_none()
+    );
+}
+
+#[test]
+fn other_topics_and_unknown_statuses_are_ignored() {
+    let tracker = PresenceTracker::new(TopicLayout::with_prefix("argus/devices/"), None);
+    let now = Utc::now();
+    assert!(
+        tracker
+            .observe(&message("argus/devices/a/alerts", json!({}), now))
+            .is_none()
+    );
+    assert!(
+        tracker
+            .observe(&message("argus/devices/a/status", json!("booting"), now))
+            .is_none()
+    );
+    assert!(tracker.get("a").is_none());
+}
+
+#[test]
+fn silent_devices_time_out() {
+    let tracker = PresenceTracker::new(
+        TopicLayout::with_prefix("argus/devices/"),
+        Some(Duration::from_secs(60)),
+    );
+    let base = Utc::now();
+    tracker.observe(&message("argus/devices/a", json!({}), base));
+
+    assert!(tracker.sweep(base + ChronoDuration::seconds(30)).is_empty());
+    let expired = tracker.sweep(base + ChronoDuration::seconds(90));
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].source, PresenceSource::Timeout);
+    assert_eq!(tracker.get("a").unwrap().state, PresenceState::Offline);
+    // Already offline: nothing more to report
+    assert!(
+        tracker
+            .sweep(base + ChronoDuration::seconds(300))
+            .is_empty()
+    );
+}
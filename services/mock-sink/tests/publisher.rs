@@ -0,0 +1,156 @@
+use crate::handlers::{AppState, telemetry};
+use crate::publisher::Publisher;
+use crate::schema::SchemaRegistry;
+use crate::types::Delivery;
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+};
+use mqtt_client::{Client, Properties};
+use rumqttc::{AsyncClient, EventLoop, MqttOptions};
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::oneshot::error::TryRecvError;
+
+/// A publisher whose client accepts requests; nothing polls the event loop,
+/// so the tests drive the packet id hooks themselves.
+fn publisher() -> (Publisher, EventLoop) {
+    let (client, eventloop) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 16);
+    (Publisher::new(Client::V4(client)), eventloop)
+}
+
+#[tokio::test]
+async fn acks_resolve_the_publish_with_that_packet_id() {
+    let (publisher, _eventloop) = publisher();
+    publisher
+        .publish("t/1".into(), b"1".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    let mut second = publisher
+        .publish_confirmed("t/2".into(), b"2".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    let mut third = publisher
+        .publish_confirmed("t/3".into(), b"3".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    for pkid in [1, 2, 3] {
+        publisher.on_outgoing_publish(pkid);
+    }
+    // A retransmit after reconnect keeps its packet id
+    publisher.on_outgoing_publish(2);
+
+    publisher.on_puback(3);
+    assert_eq!(third.try_recv(), Ok(Ok(())));
+    assert_eq!(second.try_recv(), Err(TryRecvError::Empty));
+    publisher.on_puback(1);
+    assert_eq!(second.try_recv(), Err(TryRecvError::Empty));
+    publisher.on_puback(2);
+    assert_eq!(second.try_recv(), Ok(Ok(())));
+}
+
+#[tokio::test]
+async fn colliding_packet_id_waits_for_its_own_ack() {
+    let (publisher, _eventloop) = publisher();
+    publisher
+        .publish("t/old".into(), b"old".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    publisher.on_outgoing_publish(1);
+
+    // rumqttc holds the next publish back while pkid 1 is still in flight
+    let mut next = publisher
+        .publish_confirmed("t/new".into(), b"new".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    publisher.on_await_ack(1);
+    publisher.on_puback(1);
+    assert_eq!(next.try_recv(), Err(TryRecvError::Empty));
+
+    publisher.on_outgoing_publish(1);
+    publisher.on_puback(1);
+    assert_eq!(next.try_recv(), Ok(Ok(())));
+}
+
+#[tokio::test]
+async fn broker_rejection_fails_the_oldest_unacknowledged_publish() {
+    let (publisher, _eventloop) = publisher();
+    let mut first = publisher
+        .publish_confirmed("t/1".into(), b"1".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    let mut second = publisher
+        .publish_confirmed("t/2".into(), b"2".to_vec(), Properties::default())
+        .await
+        .unwrap();
+    publisher.on_outgoing_publish(1);
+    publisher.on_outgoing_publish(2);
+
+    publisher.on_rejected("NotAuthorized");
+    assert_eq!(first.try_recv(), Ok(Err("NotAuthorized".into())));
+    assert_eq!(second.try_recv(), Err(TryRecvError::Empty));
+    publisher.on_puback(2);
+    assert_eq!(second.try_recv(), Ok(Ok(())));
+}
+
+fn confirming_state(publisher: Publisher) -> Arc<AppState> {
+    let state = AppState {
+        mqtt: publisher,
+        publish_confirm: true,
+        publish_confirm_timeout: Duration::from_millis(200),
+        ..(*super::test_state(SchemaRegistry::default())).clone()
+    };
+    state.connection.on_connack();
+    Arc::new(state)
+}
+
+fn json_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    headers
+}
+
+#[tokio::test]
+async fn confirmed_telemetry_waits_for_puback() {
+    let (publisher, _eventloop) = publisher();
+    let state = confirming_state(publisher.clone());
+    let body = Bytes::from(json!({"device_id": "a", "metrics": {"co2": 410}}).to_string());
+
+    let err = telemetry(State(state.clone()), json_headers(), body.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(err.status, StatusCode::GATEWAY_TIMEOUT);
+    publisher.on_outgoing_publish(1);
+
+    let broker = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        publisher.on_outgoing_publish(2);
+        publisher.on_puback(2);
+    });
+    let Json(resp) = telemetry(State(state.clone()), json_headers(), body.clone())
+        .await
+        .unwrap();
+    assert_eq!(resp.delivery, Delivery::Acknowledged);
+    broker.await.unwrap();
+
+    // The header overrides the service default
+    let mut headers = json_headers();
+    headers.insert("x-publish-confirm", "false".parse().unwrap());
+    let Json(resp) = telemetry(State(state), headers, body).await.unwrap();
+    assert_eq!(resp.delivery, Delivery::Published);
+}
+
+#[tokio::test]
+async fn confirmed_telemetry_is_not_acknowledged_from_the_outbox() {
+    let state = super::test_state(SchemaRegistry::default());
+    let mut headers = json_headers();
+    headers.insert("x-publish-confirm", "true".parse().unwrap());
+    let body = json!({"device_id": "a", "metrics": {"co2": 410}});
+    let err = telemetry(State(state.clone()), headers, Bytes::from(body.to_string()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(state.outbox.depth(), 1);
+}
@@ -0,0 +1,83 @@
+use crate::codec::PayloadFormat;
+use crate::schema::SchemaRegistry;
+use serde_json::json;
+
+fn registry() -> SchemaRegistry {
+    let mut registry = SchemaRegistry::default();
+    registry
+        .add(
+            "env-sensor",
+            json!({
+                "type": "object",
+                "required": ["device_id", "metrics"],
+                "properties": {
+                    "metrics": {
+                        "type": "object",
+                        "properties": {"co2": {"type": "number", "minimum": 0}}
+                    }
+                }
+            }),
+        )
+        .unwrap();
+    registry
+        .add(
+            "status",
+            json!({
+                "x-argus-topic": "argus/devices/+/status",
+                "type": "object",
+                "required": ["state"]
+            }),
+        )
+        .unwrap();
+    registry
+}
+
+#[test]
+fn file_stem_is_the_default_device_type() {
+    let registry = registry();
+    let ok = json!({"device_id": "a", "device_type": "env-sensor", "metrics": {"co2": 400}});
+    assert!(registry.validate("argus/devices/a", &ok).is_empty());
+
+    let bad =
+        json!({"device_id": "a", "tags": {"device_type": "env-sensor"}, "metrics": {"co2": -1}});
+    let violations = registry.validate("argus/devices/a", &bad);
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].schema, "env-sensor");
+    assert_eq!(violations[0].instance_path, "/metrics/co2");
+}
+
+#[test]
+fn unmatched_payloads_are_not_validated() {
+    let registry = registry();
+    let other = json!({"device_id": "a", "device_type": "camera"});
+    assert!(registry.validate("argus/devices/a", &other).is_empty());
+    assert!(
+        registry
+            .validate_payload("argus/devices/a", PayloadFormat::Json, b"not json")
+            .is_empty()
+    );
+}
+
+#[test]
+fn topic_schemas_apply_to_matching_topics() {
+    let registry = registry();
+    assert!(
+        registry
+            .validate_payload(
+                "argus/devices/a/status",
+                PayloadFormat::Json,
+                br#"{"state":"online"}"#
+            )
+            .is_empty()
+    );
+    assert_eq!(
+        registry
+            .validate_payload("argus/devices/a/status", PayloadFormat::Json, b"{}")
+            .len(),
+        1
+    );
+    let violations =
+        registry.validate_payload("argus/devices/a/status", PayloadFormat::Json, b"online");
+    assert_eq!(violations.len(), 1);
+    assert!(violations[0].message.starts_with("payload is not JSON"));
+}
@@ -0,0 +1,209 @@
+use crate::codec::PayloadFormat;
+use crate::handlers::telemetry;
+use crate::schema::SchemaRegistry;
+use crate::senml::{resolve, to_pack, to_telemetry};
+use crate::store::{Retention, Store};
+use crate::types::{MetricValue, TelemetryIn};
+use axum::{
+    Json,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderValue, header},
+};
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+
+#[test]
+fn base_fields_resolve_into_records() {
+    let pack = json!([
+        {"bn": "urn:dev:ow:10e2073a:", "bt": 1.7e9, "bu": "Cel", "n": "temp", "v": 21.5},
+        {"n": "hum", "u": "%RH", "v": 40, "t": 60},
+        {"bn": "dev-2/", "n": "door", "vb": true}
+    ]);
+    let records = resolve(pack, Utc::now()).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].device_id.as_deref(), Some("10e2073a"));
+    assert_eq!(records[0].measurement, "temp");
+    assert_eq!(records[0].unit.as_deref(), Some("Cel"));
+    assert_eq!(records[1].unit.as_deref(), Some("%RH"));
+    assert_eq!(records[1].time, 1.7e9 + 60.0);
+    assert_eq!(records[2].device_id.as_deref(), Some("dev-2"));
+    assert_eq!(records[2].value, MetricValue::Bool(true));
+    // Base unit carries over until replaced
+    assert_eq!(records[2].unit.as_deref(), Some("Cel"));
+}
+
+#[test]
+fn relative_times_and_base_values() {
+    let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+    let pack = json!([{"bn": "a/", "bv": 10, "n": "level", "v": 2.5, "t": -5}]);
+    let records = resolve(pack, now).unwrap();
+    assert_eq!(records[0].time, 1_699_999_995.0);
+    assert_eq!(
+        records[0].value,
+        MetricValue::Number(serde_json::Number::from_f64(12.5).unwrap())
+    );
+
+    assert!(resolve(json!([{"n": "x"}]), now).is_err());
+    assert!(resolve(json!({"n": "x", "v": 1}), now).is_err());
+}
+
+#[test]
+fn records_group_by_device_and_time() {
+    let pack = json!([
+        {"bn": "a/", "bt": 1.7e9, "n": "temp", "u": "Cel", "v": 21},
+        {"n": "pm25", "v": 9},
+        {"bn": "b/", "n": "temp", "v": 19}
+    ]);
+    let docs = to_telemetry(resolve(pack, Utc::now()).unwrap());
+    assert_eq!(
+        docs,
+        vec![
+            json!({"device_id": "a", "ts": 1_700_000_000, "ts_ms": 1_700_000_000_000_i64, "metrics": {"temp": 21, "pm25": 9}, "units": {"temp": "Cel"}}),
+            json!({"device_id": "b", "ts": 1_700_000_000, "ts_ms": 1_700_000_000_000_i64, "metrics": {"temp": 19}}),
+        ]
+    );
+}
+
+#[test]
+fn records_within_a_second_stay_apart() {
+    let pack = json!([
+        {"bn": "a/", "bt": 1.7e9, "n": "temp", "v": 21},
+        {"n": "temp", "v": 22, "t": 0.2}
+    ]);
+    let docs = to_telemetry(resolve(pack, Utc::now()).unwrap());
+    assert_eq!(docs.len(), 2);
+    assert_eq!(docs[0]["ts"], docs[1]["ts"]);
+    assert_eq!(docs[0]["ts_ms"], 1_700_000_000_000_i64);
+    assert_eq!(docs[1]["ts_ms"], 1_700_000_000_200_i64);
+
+    let readings: Vec<TelemetryIn> = docs
+        .into_iter()
+        .map(|doc| {
+            let mut doc: TelemetryIn = serde_json::from_value(doc).unwrap();
+            doc.device_id = "a".into();
+            doc.seq = Some(1);
+            doc
+        })
+        .collect();
+    assert_ne!(readings[0].dedup_key(), readings[1].dedup_key());
+    assert!(!readings[0].resolved_metrics().contains_key("ts_ms"));
+}
+
+#[test]
+fn senml_cbor_uses_integer_labels() {
+    // [{-2: "a/", -3: 1.7e9, 0: "temp", 2: 21}]
+    let value = ciborium::Value::Array(vec![ciborium::Value::Map(vec![
+        (
+            ciborium::Value::Integer((-2).into()),
+            ciborium::Value::Text("a/".into()),
+        ),
+        (
+            ciborium::Value::Integer((-3).into()),
+            ciborium::Value::Float(1.7e9),
+        ),
+        (
+            ciborium::Value::Integer(0.into()),
+            ciborium::Value::Text("temp".into()),
+        ),
+        (
+            ciborium::Value::Integer(2.into()),
+            ciborium::Value::Integer(21.into()),
+        ),
+    ])]);
+    let mut raw = Vec::new();
+    ciborium::into_writer(&value, &mut raw).unwrap();
+    assert_eq!(
+        PayloadFormat::SenmlCbor.decode(&raw).unwrap(),
+        json!([{"device_id": "a", "ts": 1_700_000_000, "ts_ms": 1_700_000_000_000_i64, "metrics": {"temp": 21}}])
+    );
+}
+
+#[tokio::test]
+async fn telemetry_forwards_each_device_in_a_pack() {
+    let state = super::test_state(SchemaRegistry::default());
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/senml+json"),
+    );
+    let body = json!([
+        {"bn": "a/", "n": "temp", "v": 21},
+        {"bn": "b/", "n": "temp", "v": 19}
+    ]);
+    let Json(resp) = telemetry(State(state), headers, Bytes::from(body.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(resp.forwarded_topic, "argus/devices/a");
+    assert_eq!(
+        resp.forwarded_topics,
+        vec!["argus/devices/a", "argus/devices/b"]
+    );
+}
+
+#[tokio::test]
+async fn stored_telemetry_is_reemitted_as_senml() {
+    let store = Store::open_in_memory(Retention::default()).unwrap();
+    let reading = json!({"device_id": "a", "ts": 1_700_000_000, "ts_ms": 1_700_000_000_000_i64, "metrics": {"co2": 410, "ok": true}, "units": {"co2": "ppm"}});
+    store
+        .insert(
+            &super::consumed("argus/devices/a", reading.to_string().as_bytes()),
+            Some("a"),
+            PayloadFormat::Json,
+            Utc::now(),
+            vec![],
+        )
+        .await
+        .unwrap();
+    let latest = store
+        .latest("a", vec!["argus/devices/a".into()])
+        .await
+        .unwrap()
+        .unwrap();
+
+    let pack = to_pack(&[latest]);
+    assert_eq!(
+        pack,
+        vec![
+            json!({"bn": "a/", "bt": 1.7e9, "n": "co2", "u": "ppm", "v": 410}),
+            json!({"n": "ok", "vb": true}),
+        ]
+    );
+    // The pack resolves back to the original reading
+    assert_eq!(
+        to_telemetry(resolve(json!(pack), Utc::now()).unwrap()),
+        vec![
+            json!({"device_id": "a", "ts": 1_700_000_000, "ts_ms": 1_700_000_000_000_i64, "metrics": {"co2": 410, "ok": true}, "units": {"co2": "ppm"}})
+        ]
+    );
+}
+
+#[tokio::test]
+async fn stored_relative_times_resolve_against_receive_time() {
+    let store = Store::open_in_memory(Retention::default()).unwrap();
+    let received_at = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+    let pack = json!([{"bn": "a/", "n": "co2", "t": -5, "v": 410}]);
+    store
+        .insert(
+            &super::consumed("argus/devices/a/senml", pack.to_string().as_bytes()),
+            Some("a"),
+            PayloadFormat::SenmlJson,
+            received_at,
+            vec![],
+        )
+        .await
+        .unwrap();
+
+    let read = || async {
+        store
+            .latest("a", vec!["argus/devices/a/senml".into()])
+            .await
+            .unwrap()
+            .unwrap()
+            .payload
+    };
+    let first = read().await;
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(read().await, first);
+    assert_eq!(first[0]["ts"], 1_699_999_995);
+}
@@ -0,0 +1,121 @@
+use crate::schema::SchemaRegistry;
+use crate::shadow::{
+    Document, ShadowError, ShadowStore, ShadowUpdate, delta, get_shadow, merge, update_shadow,
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+
+fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        other => panic!("not an object: {other}"),
+    }
+}
+
+#[test]
+fn merge_patches_nested_documents_and_null_deletes() {
+    let mut doc = object(json!({"interval": 30, "led": {"color": "red", "on": true}}));
+    merge(
+        &mut doc,
+        &object(json!({"interval": null, "led": {"on": false}, "fw": "1.2"})),
+    );
+    assert_eq!(
+        Value::Object(doc),
+        json!({"led": {"color": "red", "on": false}, "fw": "1.2"})
+    );
+}
+
+#[test]
+fn delta_lists_only_unreported_desired_values() {
+    let desired =
+        object(json!({"interval": 60, "led": {"color": "blue", "on": true}, "fw": "1.2"}));
+    let reported =
+        object(json!({"interval": 60, "led": {"color": "red", "on": true}, "uptime": 5}));
+    assert_eq!(
+        Value::Object(delta(&desired, &reported)),
+        json!({"led": {"color": "blue"}, "fw": "1.2"})
+    );
+}
+
+#[test]
+fn delta_events_follow_desired_and_reported_changes() {
+    let shadows = ShadowStore::default();
+    let now = Utc::now();
+    let desired = object(json!({"interval": 60}));
+
+    let (shadow, event) = shadows
+        .update("a", Document::Desired, &desired, None, now)
+        .unwrap();
+    assert_eq!(shadow.version, 1);
+    assert_eq!(Value::Object(event.unwrap().delta), json!({"interval": 60}));
+
+    // Unrelated reports leave the delta unchanged, so nothing is re-published
+    let (_, event) = shadows
+        .update(
+            "a",
+            Document::Reported,
+            &object(json!({"uptime": 5})),
+            None,
+            now,
+        )
+        .unwrap();
+    assert!(event.is_none());
+
+    let (shadow, event) = shadows
+        .update("a", Document::Reported, &desired, None, now)
+        .unwrap();
+    assert!(event.is_none());
+    assert!(shadow.delta.is_empty());
+    assert_eq!(shadow.version, 3);
+
+    let err = shadows
+        .update("a", Document::Desired, &desired, Some(1), now)
+        .unwrap_err();
+    assert_eq!(err, ShadowError::VersionConflict { current: 3 });
+}
+
+#[tokio::test]
+async fn rest_api_sets_desired_state() {
+    let state = super::test_state(SchemaRegistry::default());
+    let missing = get_shadow(State(state.clone()), Path("a".into()))
+        .await
+        .unwrap_err();
+    assert_eq!(missing.0, StatusCode::NOT_FOUND);
+
+    let update = |body: Value| -> ShadowUpdate { serde_json::from_value(body).unwrap() };
+    let Json(shadow) = update_shadow(
+        State(state.clone()),
+        Path("a".into()),
+        Json(update(json!({"desired": {"interval": 60}, "version": 0}))),
+    )
+    .await
+    .unwrap();
+    assert_eq!(shadow.version, 1);
+    assert_eq!(Value::Object(shadow.delta), json!({"interval": 60}));
+
+    let stale = update_shadow(
+        State(state.clone()),
+        Path("a".into()),
+        Json(update(json!({"desired": {"interval": 30}, "version": 0}))),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(stale.0, StatusCode::CONFLICT);
+
+    let reported = update_shadow(
+        State(state.clone()),
+        Path("a".into()),
+        Json(update(json!({"reported": {"interval": 30}}))),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(reported.0, StatusCode::BAD_REQUEST);
+
+    let Json(shadow) = get_shadow(State(state), Path("a".into())).await.unwrap();
+    assert_eq!(Value::Object(shadow.desired), json!({"interval": 60}));
+}
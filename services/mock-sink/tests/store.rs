@@ -0,0 +1,359 @@
+use crate::codec::PayloadFormat;
+use crate::schema::Violation;
+use crate::store::{
+    MessageProperties, MessageQuery, ReceivedQuery, Retention, Store, device_id_from_topic,
+    is_telemetry,
+};
+use chrono::{Duration as ChronoDuration, Utc};
+use mqtt_client::Properties;
+use std::time::Duration;
+use topic_layout::{Templates, TopicLayout};
+
+#[test]
+fn device_id_is_parsed_from_topic() {
+    let topics = TopicLayout::with_prefix("argus/devices/");
+    assert_eq!(
+        device_id_from_topic(&topics, "argus/devices/dev-1"),
+        Some("dev-1")
+    );
+    assert_eq!(
+        device_id_from_topic(&topics, "argus/devices/dev-1/status"),
+        Some("dev-1")
+    );
+    assert_eq!(device_id_from_topic(&topics, "argus/devices/"), None);
+    assert_eq!(device_id_from_topic(&topics, "other/dev-1"), None);
+}
+
+#[test]
+fn telemetry_topics_follow_the_layout() {
+    let topics = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            telemetry: Some("{tenant}/{device_id}/telemetry/{channel}".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap();
+    assert!(is_telemetry(&topics, "acme/dev-1/telemetry/env", "dev-1"));
+    assert!(is_telemetry(
+        &topics,
+        "acme/dev-1/telemetry/env/cbor",
+        "dev-1"
+    ));
+    assert!(!is_telemetry(
+        &topics,
+        "acme/dev-1/telemetry/env/status",
+        "dev-1"
+    ));
+    assert!(!is_telemetry(&topics, "argus/devices/dev-1", "dev-1"));
+    assert_eq!(
+        device_id_from_topic(&topics, "acme/dev-1/telemetry/env"),
+        Some("dev-1")
+    );
+    // Other device topics stay under the prefix
+    assert_eq!(
+        device_id_from_topic(&topics, "argus/devices/dev-1/status"),
+        Some("dev-1")
+    );
+}
+
+#[tokio::test]
+async fn retention_prunes_by_age() {
+    let store = Store::open_in_memory(Retention {
+        max_age: Some(Duration::from_secs(60)),
+        max_messages: None,
+    })
+    .unwrap();
+    let now = Utc::now();
+    store
+        .insert(
+            &super::consumed("argus/devices/a", b"{}"),
+            Some("a"),
+            PayloadFormat::Json,
+            now - ChronoDuration::minutes(5),
+            vec![],
+        )
+        .await
+        .unwrap();
+    store
+        .insert(
+            &super::consumed("argus/devices/a", b"{}"),
+            Some("a"),
+            PayloadFormat::Json,
+            now,
+            vec![],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(store.prune(now).await.unwrap(), 1);
+    assert_eq!(store.count().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn retention_keeps_newest_messages() {
+    let store = Store::open_in_memory(Retention {
+        max_age: None,
+        max_messages: Some(2),
+    })
+    .unwrap();
+    let now = Utc::now();
+    for _ in 0..5 {
+        store
+            .insert(
+                &super::consumed("argus/devices/a", b"ok"),
+                Some("a"),
+                PayloadFormat::Json,
+                now,
+                vec![],
+            )
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(store.prune(now).await.unwrap(), 3);
+    assert_eq!(store.count().await.unwrap(), 2);
+}
+
+async fn seeded_store() -> (Store, chrono::DateTime<Utc>) {
+    let store = Store::open_in_memory(Retention::default()).unwrap();
+    let base = Utc::now() - ChronoDuration::minutes(10);
+    for i in 0..5 {
+        let payload = format!("{{\"seq\":{i}}}");
+        store
+            .insert(
+                &super::consumed("argus/devices/a", payload.as_bytes()),
+                Some("a"),
+                PayloadFormat::Json,
+                base + ChronoDuration::minutes(i),
+                vec![],
+            )
+            .await
+            .unwrap();
+    }
+    store
+        .insert(
+            &super::consumed("argus/devices/a/status", b"online"),
+            Some("a"),
+            PayloadFormat::Json,
+            base,
+            vec![],
+        )
+        .await
+        .unwrap();
+    store
+        .insert(
+            &super::consumed("argus/devices/b", b"plain"),
+            Some("b"),
+            PayloadFormat::Json,
+            base,
+            vec![],
+        )
+        .await
+        .unwrap();
+    (store, base)
+}
+
+fn telemetry_query(limit: u32) -> MessageQuery {
+    MessageQuery {
+        device_id: "a".into(),
+        topics: vec!["argus/devices/a".into()],
+        limit,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn device_messages_filters_time_range_in_order() {
+    let (store, base) = seeded_store().await;
+    let rows = store
+        .device_messages(MessageQuery {
+            from: Some(base + ChronoDuration::minutes(1)),
+            to: Some(base + ChronoDuration::minutes(3)),
+            ..telemetry_query(10)
+        })
+        .await
+        .unwrap();
+    let seqs: Vec<_> = rows.iter().map(|m| m.payload["seq"].clone()).collect();
+    assert_eq!(seqs, vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn device_messages_without_from_returns_newest() {
+    let (store, _) = seeded_store().await;
+    let rows = store.device_messages(telemetry_query(2)).await.unwrap();
+    let seqs: Vec<_> = rows.iter().map(|m| m.payload["seq"].clone()).collect();
+    assert_eq!(seqs, vec![3, 4]);
+
+    let latest = store
+        .latest("a", vec!["argus/devices/a".into()])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.payload["seq"], 4);
+}
+
+#[tokio::test]
+async fn devices_summarise_activity() {
+    let (store, base) = seeded_store().await;
+    let devices = store.devices().await.unwrap();
+    assert_eq!(devices.len(), 2);
+    assert_eq!(devices[0].device_id, "a");
+    assert_eq!(devices[0].message_count, 6);
+    assert_eq!(
+        devices[0].first_seen.timestamp_millis(),
+        base.timestamp_millis()
+    );
+    assert_eq!(devices[1].device_id, "b");
+    assert_eq!(
+        store.latest("b", vec![]).await.unwrap().unwrap().payload,
+        "plain"
+    );
+}
+
+#[tokio::test]
+async fn received_filters_by_topic_since_and_contents() {
+    let (store, base) = seeded_store().await;
+    let query = |topic: &str, contains: Option<&str>| ReceivedQuery {
+        topic: Some(topic.into()),
+        since: Some(base + ChronoDuration::minutes(2)),
+        contains: contains.map(Into::into),
+        limit: 10,
+    };
+
+    let rows = store
+        .received(query("argus/devices/+", None))
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 3);
+    let rows = store
+        .received(query("argus/devices/a", Some("\"seq\":3")))
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].payload["seq"], 3);
+    let rows = store
+        .received(ReceivedQuery {
+            contains: Some("online".into()),
+            limit: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(rows[0].topic, "argus/devices/a/status");
+}
+
+#[tokio::test]
+async fn received_pages_past_non_matching_rows() {
+    let store = Store::open_in_memory(Retention::default()).unwrap();
+    let at = Utc::now();
+    for _ in 0..1200 {
+        store
+            .insert(
+                &super::consumed("other/devices/x", b"{}"),
+                Some("x"),
+                PayloadFormat::Json,
+                at,
+                vec![],
+            )
+            .await
+            .unwrap();
+    }
+    for seq in 0..2 {
+        let payload = format!("{{\"seq\":{seq}}}");
+        store
+            .insert(
+                &super::consumed("other/devices/y", payload.as_bytes()),
+                Some("y"),
+                PayloadFormat::Json,
+                at,
+                vec![],
+            )
+            .await
+            .unwrap();
+    }
+
+    let rows = store
+        .received(ReceivedQuery {
+            contains: Some("\"seq\"".into()),
+            limit: 1,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].payload["seq"], 0);
+    let rows = store
+        .received(ReceivedQuery {
+            topic: Some("other/+/y".into()),
+            limit: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+}
+
+#[tokio::test]
+async fn invalid_messages_are_counted_separately() {
+    let (store, base) = seeded_store().await;
+    let violation = Violation {
+        schema: "status".into(),
+        instance_path: String::new(),
+        message: "\"state\" is a required property".into(),
+    };
+    store
+        .insert(
+            &super::consumed("argus/devices/a/status", b"{}"),
+            Some("a"),
+            PayloadFormat::Json,
+            base,
+            vec![violation.clone()],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(store.count_invalid().await.unwrap(), 1);
+    let invalid = store.invalid(10).await.unwrap();
+    assert_eq!(invalid.len(), 1);
+    assert_eq!(invalid[0].violations, vec![violation]);
+}
+
+#[tokio::test]
+async fn mqtt_v5_properties_are_stored_with_the_message() {
+    let store = Store::open_in_memory(Retention::default()).unwrap();
+    let mut message = super::consumed("argus/devices/a/commands/response", b"{}");
+    message.properties = Properties {
+        content_type: Some("application/json".into()),
+        correlation_data: Some(b"req-1".to_vec()),
+        user_properties: vec![("fw".into(), "1.2.0".into())],
+        ..Properties::default()
+    };
+    let stored = store
+        .insert(&message, Some("a"), PayloadFormat::Json, Utc::now(), vec![])
+        .await
+        .unwrap();
+    let expected = MessageProperties {
+        content_type: Some("application/json".into()),
+        correlation_data: Some("req-1".into()),
+        user_properties: vec![("fw".into(), "1.2.0".into())],
+        ..MessageProperties::default()
+    };
+    assert_eq!(stored.properties.as_ref(), Some(&expected));
+
+    let plain = super::consumed("argus/devices/a", b"{}");
+    store
+        .insert(&plain, Some("a"), PayloadFormat::Json, Utc::now(), vec![])
+        .await
+        .unwrap();
+    let rows = store
+        .received(ReceivedQuery {
+            limit: 10,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].properties, Some(expected));
+    assert_eq!(rows[1].properties, None);
+}
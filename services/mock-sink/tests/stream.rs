@@ -0,0 +1,38 @@
+use crate::store::StoredMessage;
+use crate::stream::StreamFilter;
+use chrono::Utc;
+use serde_json::json;
+
+fn message(topic: &str, device_id: Option<&str>) -> StoredMessage {
+    StoredMessage {
+        id: 1,
+        topic: topic.into(),
+        device_id: device_id.map(str::to_string),
+        payload: json!({}),
+        encoding: None,
+        received_at: Utc::now(),
+        violations: Vec::new(),
+        properties: None,
+    }
+}
+
+#[test]
+fn filters_by_device_and_topic() {
+    let all = StreamFilter::default();
+    assert!(all.matches(&message("argus/devices/a", Some("a"))));
+
+    let device = StreamFilter {
+        device_id: Some("a".into()),
+        topic: None,
+    };
+    assert!(device.matches(&message("argus/devices/a/status", Some("a"))));
+    assert!(!device.matches(&message("argus/devices/b", Some("b"))));
+    assert!(!device.matches(&message("other", None)));
+
+    let topic = StreamFilter {
+        device_id: None,
+        topic: Some("argus/devices/+/status".into()),
+    };
+    assert!(topic.matches(&message("argus/devices/b/status", Some("b"))));
+    assert!(!topic.matches(&message("argus/devices/b", Some("b"))));
+}
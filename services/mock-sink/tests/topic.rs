@@ -0,0 +1,51 @@
+use crate::topic::{is_valid_filter, is_wildcard, matches_filter};
+
+#[test]
+fn exact_filters_match_only_the_same_topic() {
+    assert!(matches_filter("argus/devices/a", "argus/devices/a"));
+    assert!(!matches_filter("argus/devices/a", "argus/devices/b"));
+    assert!(!matches_filter("argus/devices/a", "argus/devices/a/status"));
+}
+
+#[test]
+fn single_level_wildcard() {
+    assert!(matches_filter("argus/devices/+", "argus/devices/a"));
+    assert!(matches_filter(
+        "argus/devices/+/status",
+        "argus/devices/a/status"
+    ));
+    assert!(!matches_filter("argus/devices/+", "argus/devices/a/status"));
+    assert!(!matches_filter(
+        "argus/devices/+/status",
+        "argus/devices/status"
+    ));
+}
+
+#[test]
+fn multi_level_wildcard() {
+    assert!(matches_filter("argus/devices/#", "argus/devices/a"));
+    assert!(matches_filter(
+        "argus/devices/#",
+        "argus/devices/a/ota/status"
+    ));
+    assert!(matches_filter("argus/devices/#", "argus/devices"));
+    assert!(matches_filter("#", "anything/at/all"));
+    assert!(!matches_filter("argus/devices/#", "other/devices/a"));
+}
+
+#[test]
+fn wildcard_detection() {
+    assert!(is_wildcard("argus/+/x"));
+    assert!(is_wildcard("argus/#"));
+    assert!(!is_wildcard("argus/devices/a+b"));
+}
+
+#[test]
+fn filter_validity() {
+    assert!(is_valid_filter("argus/devices/#"));
+    assert!(is_valid_filter("argus/+/status"));
+    assert!(is_valid_filter("#"));
+    assert!(!is_valid_filter(""));
+    assert!(!is_valid_filter("argus/#/status"));
+    assert!(!is_valid_filter("argus/dev+"));
+}
@@ -0,0 +1,64 @@
+use crate::types::{MetricValue, TelemetryIn};
+use serde_json::{Number, Value, json};
+
+fn number(v: f64) -> MetricValue {
+    MetricValue::Number(Number::from_f64(v).unwrap())
+}
+
+#[test]
+fn legacy_flat_payload_is_accepted() {
+    let body: TelemetryIn = serde_json::from_value(json!({
+        "device_id": "dev-1",
+        "temp": 21.3,
+        "pm25": 10,
+        "ts": 1700000000
+    }))
+    .unwrap();
+    let metrics = body.resolved_metrics();
+    assert_eq!(metrics["temp"], number(21.3));
+    assert_eq!(metrics["pm25"], MetricValue::Number(10.into()));
+    assert!(!metrics.contains_key("noise"));
+
+    let forwarded = serde_json::to_value(&body).unwrap();
+    assert_eq!(forwarded["temp"], json!(21.3));
+    assert!(forwarded.get("noise").is_none());
+}
+
+#[test]
+fn metrics_units_tags_and_unknown_fields_survive_forwarding() {
+    let input = json!({
+        "device_id": "dev-1",
+        "metrics": {"co2": 412, "humidity": 40.5, "door_open": false, "mode": "eco"},
+        "units": {"co2": "ppm", "humidity": "%RH"},
+        "tags": {"room": "lab"},
+        "battery": 3.7,
+        "tenant": "acme",
+        "firmware": {"version": "1.2.0"}
+    });
+    let body: TelemetryIn = serde_json::from_value(input.clone()).unwrap();
+
+    let forwarded: Value = serde_json::to_value(&body).unwrap();
+    for key in ["metrics", "units", "tags", "battery", "tenant", "firmware"] {
+        assert_eq!(forwarded[key], input[key], "{key} was not forwarded");
+    }
+
+    let metrics = body.resolved_metrics();
+    assert_eq!(metrics["co2"], MetricValue::Number(412.into()));
+    assert_eq!(metrics["door_open"], MetricValue::Bool(false));
+    assert_eq!(metrics["mode"], MetricValue::Text("eco".into()));
+    // flat fields other than the legacy ones are forwarded, not measured
+    assert!(!metrics.contains_key("battery"));
+    assert!(!metrics.contains_key("tenant"));
+    assert!(!metrics.contains_key("firmware"));
+}
+
+#[test]
+fn explicit_metrics_win_over_flat_fields() {
+    let body: TelemetryIn = serde_json::from_value(json!({
+        "device_id": "dev-1",
+        "temp": 20.0,
+        "metrics": {"temp": 22.0}
+    }))
+    .unwrap();
+    assert_eq!(body.resolved_metrics()["temp"], number(22.0));
+}
@@ -0,0 +1,688 @@
+use bytes::Bytes;
+use rand::Rng;
+use rumqttc::v5::{
+    self,
+    mqttbytes::v5::{Packet, PublishProperties},
+};
+use rumqttc::{Outgoing, QoS, TlsConfiguration, Transport};
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::watch;
+use url::Url;
+
+/// MQTT protocol version spoken to the broker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// MQTT 3.1.1 (protocol level 4).
+    #[default]
+    V4,
+    V5,
+}
+
+impl Protocol {
+    /// `3.1.1`, `4` or `v4`; `5` or `v5`.
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "3.1.1" | "4" | "v4" => Some(Self::V4),
+            "5" | "5.0" | "v5" => Some(Self::V5),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::V4 => "3.1.1",
+            Self::V5 => "5",
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Publish properties. They only go on the wire with MQTT v5; v3.1.1
+/// publishes drop them and consumed v3.1.1 messages have none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Properties {
+    pub content_type: Option<String>,
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    pub user_properties: Vec<(String, String)>,
+    /// Message expiry interval in seconds.
+    pub message_expiry: Option<u32>,
+}
+
+impl Properties {
+    pub fn with_content_type(content_type: &str) -> Self {
+        Self {
+            content_type: Some(content_type.to_string()),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl From<Properties> for PublishProperties {
+    fn from(properties: Properties) -> Self {
+        Self {
+            message_expiry_interval: properties.message_expiry,
+            response_topic: properties.response_topic,
+            correlation_data: properties.correlation_data.map(Bytes::from),
+            user_properties: properties.user_properties,
+            content_type: properties.content_type,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<PublishProperties> for Properties {
+    fn from(properties: PublishProperties) -> Self {
+        Self {
+            content_type: properties.content_type,
+            response_topic: properties.response_topic,
+            correlation_data: properties.correlation_data.map(|data| data.to_vec()),
+            user_properties: properties.user_properties,
+            message_expiry: properties.message_expiry_interval,
+        }
+    }
+}
+
+/// Transport named by the broker URL scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// `mqtt://` (or `tcp://`), plain TCP.
+    Tcp,
+    /// `mqtts://` (or `ssl://`, `tls://`).
+    Tls,
+    /// `ws://`, MQTT over WebSocket.
+    Ws,
+    /// `wss://`, MQTT over WebSocket with TLS.
+    Wss,
+}
+
+impl Scheme {
+    pub fn parse(scheme: &str) -> Option<Self> {
+        match scheme.to_ascii_lowercase().as_str() {
+            "mqtt" | "tcp" => Some(Self::Tcp),
+            "mqtts" | "ssl" | "tls" => Some(Self::Tls),
+            "ws" => Some(Self::Ws),
+            "wss" => Some(Self::Wss),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tcp => "mqtt",
+            Self::Tls => "mqtts",
+            Self::Ws => "ws",
+            Self::Wss => "wss",
+        }
+    }
+
+    pub fn is_tls(self) -> bool {
+        matches!(self, Self::Tls | Self::Wss)
+    }
+
+    pub fn is_websocket(self) -> bool {
+        matches!(self, Self::Ws | Self::Wss)
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            Self::Tcp => 1883,
+            Self::Tls => 8883,
+            Self::Ws => 80,
+            Self::Wss => 443,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EndpointError {
+    InvalidUrl(String),
+    UnsupportedScheme(String),
+    MissingHost,
+}
+
+impl fmt::Display for EndpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(reason) => write!(f, "invalid broker URL: {reason}"),
+            Self::UnsupportedScheme(scheme) => write!(
+                f,
+                "unsupported broker URL scheme '{scheme}' (expected mqtt, mqtts, ws or wss)"
+            ),
+            Self::MissingHost => f.write_str("broker URL has no host"),
+        }
+    }
+}
+
+impl std::error::Error for EndpointError {}
+
+/// A certificate file named by an environment variable that could not be read.
+#[derive(Debug)]
+pub struct TlsError {
+    pub var: &'static str,
+    pub path: String,
+    pub source: std::io::Error,
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to read {} at {}: {}",
+            self.var, self.path, self.source
+        )
+    }
+}
+
+impl std::error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn read_cert(var: &'static str, path: String) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(&path).map_err(|source| TlsError { var, path, source })
+}
+
+/// Where the broker listens, e.g. `mqtts://mqtt:8883` or
+/// `wss://ingress.example/mqtt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub scheme: Scheme,
+    pub host: String,
+    pub port: u16,
+    /// Request path (and query) of a WebSocket endpoint; `/` otherwise.
+    pub path: String,
+}
+
+impl Endpoint {
+    /// Parse a broker URL; without a port the scheme's default is used.
+    pub fn parse(raw: &str) -> Result<Self, EndpointError> {
+        let url = Url::parse(raw.trim()).map_err(|e| EndpointError::InvalidUrl(e.to_string()))?;
+        let scheme = Scheme::parse(url.scheme())
+            .ok_or_else(|| EndpointError::UnsupportedScheme(url.scheme().to_string()))?;
+        let host = url
+            .host_str()
+            .filter(|h| !h.is_empty())
+            .ok_or(EndpointError::MissingHost)?;
+        let mut path = match url.path() {
+            "" => "/".to_string(),
+            path => path.to_string(),
+        };
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        Ok(Self {
+            scheme,
+            host: host.to_string(),
+            port: url.port().unwrap_or(scheme.default_port()),
+            path,
+        })
+    }
+
+    pub fn new(scheme: Scheme, host: &str, port: u16) -> Self {
+        Self {
+            scheme,
+            host: host.to_string(),
+            port,
+            path: "/".into(),
+        }
+    }
+
+    /// Plain TCP to the standard TLS port, most likely a URL written when
+    /// the scheme was still ignored and TLS was always used.
+    pub fn is_plain_on_tls_port(&self) -> bool {
+        self.scheme == Scheme::Tcp && self.port == Scheme::Tls.default_port()
+    }
+
+    /// TLS settings for `mqtts://` and `wss://` endpoints: the CA from
+    /// `MQTT_CA_PATH` (default `/certs/ca.crt`) and the optional client pair
+    /// `MQTT_CERT_PATH`/`MQTT_KEY_PATH`. `None` for plain endpoints.
+    pub fn tls_from_env(&self) -> Result<Option<TlsConfiguration>, TlsError> {
+        if !self.scheme.is_tls() {
+            return Ok(None);
+        }
+        let ca_path = env_var("MQTT_CA_PATH").unwrap_or_else(|| "/certs/ca.crt".into());
+        let ca = read_cert("MQTT_CA_PATH", ca_path)?;
+        let client_auth = match (env_var("MQTT_CERT_PATH"), env_var("MQTT_KEY_PATH")) {
+            (Some(cert_path), Some(key_path)) => Some((
+                read_cert("MQTT_CERT_PATH", cert_path)?,
+                read_cert("MQTT_KEY_PATH", key_path)?,
+            )),
+            (None, None) => None,
+            _ => {
+                tracing::warn!(
+                    "MQTT client certificate/key not fully specified; proceeding without client auth"
+                );
+                None
+            }
+        };
+        Ok(Some(TlsConfiguration::Simple {
+            ca,
+            alpn: None,
+            client_auth,
+        }))
+    }
+
+    /// Broker address for rumqttc, which takes the whole URL for WebSockets.
+    fn broker_addr(&self) -> String {
+        if self.scheme.is_websocket() {
+            self.to_string()
+        } else {
+            self.host.clone()
+        }
+    }
+
+    fn transport(&self, tls: Option<TlsConfiguration>) -> Transport {
+        match self.scheme {
+            Scheme::Tcp => Transport::Tcp,
+            Scheme::Tls => Transport::Tls(tls.unwrap_or_default()),
+            Scheme::Ws => Transport::Ws,
+            Scheme::Wss => Transport::Wss(tls.unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}:{}", self.scheme.as_str(), self.host, self.port)?;
+        if self.scheme.is_websocket() {
+            f.write_str(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Connection settings shared by both protocol versions.
+pub struct Options {
+    pub client_id: String,
+    pub endpoint: Endpoint,
+    /// Used by `mqtts` and `wss` endpoints; the platform's root
+    /// certificates when `None`.
+    pub tls: Option<TlsConfiguration>,
+    pub credentials: Option<(String, String)>,
+    pub keep_alive: Duration,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    V4(Box<rumqttc::ClientError>),
+    V5(Box<v5::ClientError>),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4(e) => e.fmt(f),
+            Self::V5(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[derive(Debug)]
+pub enum ConnectionError {
+    V4(rumqttc::ConnectionError),
+    V5(v5::ConnectionError),
+}
+
+impl ConnectionError {
+    /// Reason code of a v5 PubAck that rejected a publish. rumqttc drops the
+    /// connection without reporting the packet id, so callers can only guess
+    /// which publish it was.
+    pub fn rejected_publish(&self) -> Option<String> {
+        match self {
+            Self::V5(v5::ConnectionError::MqttState(v5::StateError::PubAckFail { reason })) => {
+                Some(format!("{reason:?}"))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4(e) => e.fmt(f),
+            Self::V5(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+/// A consumed publish.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Bytes,
+    pub properties: Properties,
+}
+
+/// Event loop notifications the services act on, the same for both
+/// protocol versions.
+#[derive(Debug)]
+pub enum Event {
+    ConnAck {
+        session_present: bool,
+    },
+    Publish(Message),
+    PubAck(u16),
+    /// Reason codes of the filters the broker refused.
+    SubAck {
+        pkid: u16,
+        refused: Vec<String>,
+    },
+    OutgoingPublish(u16),
+    /// A publish waits for the ack of an older packet with this id.
+    AwaitAck(u16),
+    /// Anything else, for trace logging.
+    Other(String),
+}
+
+#[derive(Clone)]
+pub enum Client {
+    V4(rumqttc::AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+pub enum EventLoop {
+    V4(Box<rumqttc::EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+impl Client {
+    pub fn new(protocol: Protocol, options: Options, cap: usize) -> (Self, EventLoop) {
+        let broker_addr = options.endpoint.broker_addr();
+        let port = options.endpoint.port;
+        let transport = options.endpoint.transport(options.tls);
+        match protocol {
+            Protocol::V4 => {
+                let mut opts = rumqttc::MqttOptions::new(options.client_id, broker_addr, port);
+                opts.set_keep_alive(options.keep_alive);
+                opts.set_transport(transport);
+                if let Some((username, password)) = options.credentials {
+                    opts.set_credentials(username, password);
+                }
+                let (client, eventloop) = rumqttc::AsyncClient::new(opts, cap);
+                (Self::V4(client), EventLoop::V4(Box::new(eventloop)))
+            }
+            Protocol::V5 => {
+                let mut opts = v5::MqttOptions::new(options.client_id, broker_addr, port);
+                opts.set_keep_alive(options.keep_alive);
+                opts.set_transport(transport);
+                if let Some((username, password)) = options.credentials {
+                    opts.set_credentials(username, password);
+                }
+                let (client, eventloop) = v5::AsyncClient::new(opts, cap);
+                (Self::V5(client), EventLoop::V5(Box::new(eventloop)))
+            }
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            Self::V4(_) => Protocol::V4,
+            Self::V5(_) => Protocol::V5,
+        }
+    }
+
+    /// QoS 1 publish, waiting for space in the request channel.
+    pub async fn publish(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        properties: Properties,
+    ) -> Result<(), ClientError> {
+        match self {
+            Self::V4(client) => client
+                .publish(topic, QoS::AtLeastOnce, false, payload)
+                .await
+                .map_err(|e| ClientError::V4(Box::new(e))),
+            Self::V5(client) => client
+                .publish_with_properties(
+                    topic,
+                    v5::mqttbytes::QoS::AtLeastOnce,
+                    false,
+                    payload,
+                    properties.into(),
+                )
+                .await
+                .map_err(|e| ClientError::V5(Box::new(e))),
+        }
+    }
+
+    /// Like [`Client::publish`], failing when the request channel is full.
+    pub fn try_publish(
+        &self,
+        topic: String,
+        payload: Vec<u8>,
+        properties: Properties,
+    ) -> Result<(), ClientError> {
+        match self {
+            Self::V4(client) => client
+                .try_publish(topic, QoS::AtLeastOnce, false, payload)
+                .map_err(|e| ClientError::V4(Box::new(e))),
+            Self::V5(client) => client
+                .try_publish_with_properties(
+                    topic,
+                    v5::mqttbytes::QoS::AtLeastOnce,
+                    false,
+                    payload,
+                    properties.into(),
+                )
+                .map_err(|e| ClientError::V5(Box::new(e))),
+        }
+    }
+
+    /// QoS 1 subscription.
+    pub async fn subscribe(&self, filter: &str) -> Result<(), ClientError> {
+        match self {
+            Self::V4(client) => client
+                .subscribe(filter, QoS::AtLeastOnce)
+                .await
+                .map_err(|e| ClientError::V4(Box::new(e))),
+            Self::V5(client) => client
+                .subscribe(filter, v5::mqttbytes::QoS::AtLeastOnce)
+                .await
+                .map_err(|e| ClientError::V5(Box::new(e))),
+        }
+    }
+}
+
+impl EventLoop {
+    pub async fn poll(&mut self) -> Result<Event, ConnectionError> {
+        match self {
+            Self::V4(eventloop) => eventloop
+                .poll()
+                .await
+                .map(from_v4)
+                .map_err(ConnectionError::V4),
+            Self::V5(eventloop) => eventloop
+                .poll()
+                .await
+                .map(from_v5)
+                .map_err(ConnectionError::V5),
+        }
+    }
+}
+
+/// Exponential reconnect delay with jitter: the nth retry waits between half
+/// and all of `initial * 2^n`, capped at `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    /// Delay before the first retry.
+    pub const INITIAL: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX_SECS: u64 = 30;
+
+    /// Reconnect backoff from `INITIAL` up to `max_secs` whole seconds, as in
+    /// `MQTT_RECONNECT_MAX_SECS`. Values below one second (including `0`)
+    /// keep the delay at `INITIAL`; unparsable values use the default.
+    pub fn from_max_secs(max_secs: &str) -> Self {
+        let max = max_secs.trim().parse().unwrap_or(Self::DEFAULT_MAX_SECS);
+        Self::new(Self::INITIAL, Duration::from_secs(max).max(Self::INITIAL))
+    }
+
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max: max.max(initial),
+            attempt: 0,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self
+            .initial
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        let half = ceiling / 2;
+        let jitter = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter)
+    }
+
+    /// Start over after a successful connect.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Broker connection as last seen by the event loop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStatus {
+    pub connected: bool,
+    /// When the current (or last) connection was acknowledged.
+    pub connected_since: Option<SystemTime>,
+    /// Consecutive connection errors since the last ConnAck.
+    pub failures: u32,
+    pub last_error: Option<String>,
+}
+
+/// Connection status shared between an event loop and the code that
+/// depends on it, such as health endpoints.
+#[derive(Debug, Clone)]
+pub struct ConnectionState(Arc<watch::Sender<ConnectionStatus>>);
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(ConnectionStatus::default())))
+    }
+}
+
+impl ConnectionState {
+    pub fn on_connack(&self) {
+        self.0.send_modify(|status| {
+            status.connected = true;
+            status.connected_since = Some(SystemTime::now());
+            status.failures = 0;
+        });
+    }
+
+    pub fn on_error(&self, error: &impl fmt::Display) {
+        self.0.send_modify(|status| {
+            status.connected = false;
+            status.failures = status.failures.saturating_add(1);
+            status.last_error = Some(error.to_string());
+        });
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        self.0.borrow().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.0.borrow().connected
+    }
+
+    /// Resolve once the client is connected.
+    pub async fn connected(&self) {
+        let mut rx = self.0.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = rx.wait_for(|status| status.connected).await;
+    }
+}
+
+fn from_outgoing(outgoing: Outgoing) -> Event {
+    match outgoing {
+        Outgoing::Publish(pkid) => Event::OutgoingPublish(pkid),
+        Outgoing::AwaitAck(pkid) => Event::AwaitAck(pkid),
+        other => Event::Other(format!("outgoing {other:?}")),
+    }
+}
+
+fn from_v4(event: rumqttc::Event) -> Event {
+    use rumqttc::{Incoming, SubscribeReasonCode};
+    match event {
+        rumqttc::Event::Incoming(Incoming::ConnAck(ack)) => Event::ConnAck {
+            session_present: ack.session_present,
+        },
+        rumqttc::Event::Incoming(Incoming::Publish(publish)) => Event::Publish(Message {
+            topic: publish.topic,
+            payload: publish.payload,
+            properties: Properties::default(),
+        }),
+        rumqttc::Event::Incoming(Incoming::PubAck(ack)) => Event::PubAck(ack.pkid),
+        rumqttc::Event::Incoming(Incoming::SubAck(ack)) => Event::SubAck {
+            pkid: ack.pkid,
+            refused: ack
+                .return_codes
+                .iter()
+                .filter(|code| **code == SubscribeReasonCode::Failure)
+                .map(|code| format!("{code:?}"))
+                .collect(),
+        },
+        rumqttc::Event::Incoming(other) => Event::Other(format!("incoming {other:?}")),
+        rumqttc::Event::Outgoing(outgoing) => from_outgoing(outgoing),
+    }
+}
+
+// rumqttc reports refused v5 ConnAcks, SubAcks and PubAcks as errors with
+// their reason code, so only successes arrive here
+fn from_v5(event: v5::Event) -> Event {
+    match event {
+        v5::Event::Incoming(Packet::ConnAck(ack)) => Event::ConnAck {
+            session_present: ack.session_present,
+        },
+        v5::Event::Incoming(Packet::Publish(publish)) => Event::Publish(Message {
+            topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+            payload: publish.payload,
+            properties: publish.properties.map(Properties::from).unwrap_or_default(),
+        }),
+        v5::Event::Incoming(Packet::PubAck(ack)) => Event::PubAck(ack.pkid),
+        v5::Event::Incoming(Packet::SubAck(ack)) => Event::SubAck {
+            pkid: ack.pkid,
+            refused: Vec::new(),
+        },
+        v5::Event::Incoming(other) => Event::Other(format!("incoming {other:?}")),
+        v5::Event::Outgoing(outgoing) => from_outgoing(outgoing),
+    }
+}
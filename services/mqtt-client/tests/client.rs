@@ -0,0 +1,183 @@
+use mqtt_client::{
+    Backoff, Client, ConnectionState, Endpoint, EndpointError, Options, Properties, Protocol,
+    Scheme,
+};
+use rumqttc::{TlsConfiguration, v5::mqttbytes::v5::PublishProperties};
+use std::time::Duration;
+
+fn options() -> Options {
+    Options {
+        client_id: "test".into(),
+        endpoint: Endpoint::new(Scheme::Tcp, "localhost", 1883),
+        tls: None,
+        credentials: None,
+        keep_alive: Duration::from_secs(30),
+    }
+}
+
+#[test]
+fn protocol_names() {
+    assert_eq!(Protocol::parse("3.1.1"), Some(Protocol::V4));
+    assert_eq!(Protocol::parse(" V4 "), Some(Protocol::V4));
+    assert_eq!(Protocol::parse("5"), Some(Protocol::V5));
+    assert_eq!(Protocol::parse("v5"), Some(Protocol::V5));
+    assert_eq!(Protocol::parse("3"), None);
+    assert_eq!(Protocol::default().to_string(), "3.1.1");
+}
+
+#[test]
+fn endpoint_follows_url_scheme() {
+    let plain = Endpoint::parse("mqtt://localhost").unwrap();
+    assert_eq!(plain, Endpoint::new(Scheme::Tcp, "localhost", 1883));
+    assert!(!plain.scheme.is_tls());
+
+    assert!(!plain.is_plain_on_tls_port());
+    assert!(
+        Endpoint::parse("mqtt://mqtt:8883")
+            .unwrap()
+            .is_plain_on_tls_port()
+    );
+
+    let tls = Endpoint::parse("mqtts://mqtt:9883").unwrap();
+    assert_eq!((tls.scheme, tls.port), (Scheme::Tls, 9883));
+    assert!(tls.scheme.is_tls());
+
+    let ws = Endpoint::parse("ws://ingress/mqtt?tenant=a").unwrap();
+    assert_eq!((ws.scheme, ws.port), (Scheme::Ws, 80));
+    assert_eq!(ws.to_string(), "ws://ingress:80/mqtt?tenant=a");
+
+    let wss = Endpoint::parse("WSS://ingress.example").unwrap();
+    assert_eq!((wss.scheme, wss.port), (Scheme::Wss, 443));
+    assert_eq!(wss.path, "/");
+
+    assert_eq!(
+        Endpoint::parse("http://ingress"),
+        Err(EndpointError::UnsupportedScheme("http".into()))
+    );
+    assert!(matches!(
+        Endpoint::parse("mqtt//broken"),
+        Err(EndpointError::InvalidUrl(_))
+    ));
+}
+
+#[test]
+fn tls_settings_come_from_the_environment() {
+    let dir = std::env::temp_dir().join(format!("mqtt-client-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ca = dir.join("ca.crt");
+    std::fs::write(&ca, b"ca").unwrap();
+    // SAFETY: no other test in this binary reads the MQTT_* certificate variables.
+    unsafe {
+        std::env::set_var("MQTT_CA_PATH", &ca);
+        std::env::set_var("MQTT_CERT_PATH", dir.join("client.crt"));
+        std::env::remove_var("MQTT_KEY_PATH");
+    }
+
+    let plain = Endpoint::new(Scheme::Tcp, "localhost", 1883);
+    assert!(plain.tls_from_env().unwrap().is_none());
+
+    // A half-configured client pair is ignored rather than read.
+    let tls = Endpoint::new(Scheme::Tls, "localhost", 8883);
+    match tls.tls_from_env().unwrap() {
+        Some(TlsConfiguration::Simple {
+            ca, client_auth, ..
+        }) => {
+            assert_eq!(ca, b"ca");
+            assert!(client_auth.is_none());
+        }
+        other => panic!("unexpected TLS settings: {other:?}"),
+    }
+
+    unsafe { std::env::set_var("MQTT_KEY_PATH", dir.join("client.key")) };
+    let err = tls.tls_from_env().unwrap_err();
+    assert_eq!(err.var, "MQTT_CERT_PATH");
+
+    unsafe {
+        std::env::remove_var("MQTT_CA_PATH");
+        std::env::remove_var("MQTT_CERT_PATH");
+        std::env::remove_var("MQTT_KEY_PATH");
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn properties_round_trip_through_v5() {
+    let properties = Properties {
+        content_type: Some("application/cbor".into()),
+        response_topic: Some("argus/devices/dev-1/commands/response".into()),
+        correlation_data: Some(b"abc".to_vec()),
+        user_properties: vec![("source".into(), "mock-sink".into())],
+        message_expiry: Some(30),
+    };
+    let wire = PublishProperties::from(properties.clone());
+    assert_eq!(wire.message_expiry_interval, Some(30));
+    assert_eq!(wire.correlation_data.as_deref(), Some(&b"abc"[..]));
+    assert_eq!(wire.topic_alias, None);
+    assert_eq!(Properties::from(wire), properties);
+    assert!(Properties::default().is_empty());
+}
+
+#[tokio::test]
+async fn clients_speak_the_requested_protocol() {
+    for protocol in [Protocol::V4, Protocol::V5] {
+        let (client, _eventloop) = Client::new(protocol, options(), 4);
+        assert_eq!(client.protocol(), protocol);
+        client
+            .publish(
+                "t/1".into(),
+                b"{}".to_vec(),
+                Properties::with_content_type("application/json"),
+            )
+            .await
+            .unwrap();
+        client.subscribe("t/#").await.unwrap();
+    }
+}
+
+#[test]
+fn backoff_grows_with_jitter_up_to_the_cap() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(8));
+    for ceiling in [1, 2, 4, 8, 8, 8] {
+        let ceiling = Duration::from_secs(ceiling);
+        let delay = backoff.next_delay();
+        assert!(
+            delay >= ceiling / 2 && delay <= ceiling,
+            "{delay:?} vs {ceiling:?}"
+        );
+    }
+    backoff.reset();
+    assert!(backoff.next_delay() <= Duration::from_secs(1));
+
+    for raw in ["0", "1"] {
+        let mut backoff = Backoff::from_max_secs(raw);
+        for _ in 0..4 {
+            assert!(backoff.next_delay() <= Backoff::INITIAL, "{raw}");
+        }
+    }
+    let mut backoff = Backoff::from_max_secs("soon");
+    for _ in 0..10 {
+        backoff.next_delay();
+    }
+    assert!(backoff.next_delay() >= Duration::from_secs(Backoff::DEFAULT_MAX_SECS) / 2);
+}
+
+#[tokio::test]
+async fn connection_state_tracks_failures_until_connack() {
+    let state = ConnectionState::default();
+    assert!(!state.is_connected());
+    state.on_error(&"connection refused");
+    state.on_error(&"connection refused");
+    let status = state.status();
+    assert_eq!(status.failures, 2);
+    assert_eq!(status.last_error.as_deref(), Some("connection refused"));
+    assert_eq!(status.connected_since, None);
+
+    state.on_connack();
+    tokio::time::timeout(Duration::from_secs(1), state.connected())
+        .await
+        .unwrap();
+    let status = state.status();
+    assert!(status.connected && status.connected_since.is_some());
+    assert_eq!(status.failures, 0);
+    assert_eq!(status.last_error.as_deref(), Some("connection refused"));
+}
@@ -0,0 +1,83 @@
+//! HTTP metrics shared by the services, so every `/metrics` endpoint reports
+//! requests and results with the same names and labels.
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramVec, IntCounterVec, TextEncoder, register_histogram_vec,
+    register_int_counter_vec,
+};
+use std::{sync::LazyLock, time::Instant};
+
+/// Values of every `result` label.
+pub const RESULTS: [&str; 2] = ["ok", "error"];
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests by method, route and status",
+        &["method", "route", "status"]
+    )
+    .expect("register http_requests_total")
+});
+
+pub static HTTP_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latency by method, route and status",
+        &["method", "route", "status"]
+    )
+    .expect("register http_request_duration_seconds")
+});
+
+/// Register the HTTP collectors up front so dashboards see zeroes, not gaps.
+pub fn init() {
+    LazyLock::force(&HTTP_REQUESTS);
+    LazyLock::force(&HTTP_DURATION);
+}
+
+/// `result` label of an operation: `ok` or `error`.
+pub fn result_label<T, E>(result: &Result<T, E>) -> &'static str {
+    if result.is_ok() {
+        RESULTS[0]
+    } else {
+        RESULTS[1]
+    }
+}
+
+/// Middleware counting requests and latency per matched route.
+pub async fn track(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let response = next.run(req).await;
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_DURATION
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// Prometheus text exposition of `families`.
+pub fn encode(families: &[prometheus::proto::MetricFamily]) -> Response {
+    let mut body = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(families, &mut body) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response()
+}
+
+/// Everything in the default registry.
+pub async fn render() -> Response {
+    encode(&prometheus::gather())
+}
@@ -0,0 +1,36 @@
+use axum::{Router, body::Body, http::Request, routing::get};
+use service_metrics::{render, result_label, track};
+use tower::util::ServiceExt; // for `oneshot`
+
+#[test]
+fn results_are_ok_or_error() {
+    assert_eq!(result_label(&Ok::<(), ()>(())), "ok");
+    assert_eq!(result_label(&Err::<(), ()>(())), "error");
+}
+
+#[tokio::test]
+async fn requests_are_counted_per_route() {
+    service_metrics::init();
+    let app = Router::new()
+        .route("/items/:id", get(|| async { "item" }))
+        .layer(axum::middleware::from_fn(track));
+    for id in ["a", "b"] {
+        app.clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/items/{id}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let body = axum::body::to_bytes(render().await.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        text.contains(r#"http_requests_total{method="GET",route="/items/:id",status="200"} 2"#)
+    );
+}
@@ -0,0 +1,414 @@
+use std::{collections::BTreeMap, fmt};
+
+/// The placeholder every device topic template must contain.
+pub const DEVICE_ID: &str = "device_id";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+    InvalidTemplate {
+        template: String,
+        reason: String,
+    },
+    /// `MQTT_TOPIC_VARS`-style `name=value` list that does not parse.
+    InvalidVars(String),
+    /// A placeholder with no value when building a topic.
+    MissingVar(String),
+    /// A value that would not stay within one topic level.
+    InvalidValue {
+        var: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTemplate { template, reason } => {
+                write!(f, "invalid topic template '{template}': {reason}")
+            }
+            Self::InvalidVars(entry) => {
+                write!(f, "invalid topic variable '{entry}' (expected name=value)")
+            }
+            Self::MissingVar(var) => write!(f, "topic variable '{var}' is not set"),
+            Self::InvalidValue { var, value } => write!(
+                f,
+                "topic variable '{var}' must be one non-empty level without wildcards, got '{value}'"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Level {
+    Literal(String),
+    Var(String),
+}
+
+/// A topic such as `{tenant}/{device_type}/{device_id}/telemetry`, where
+/// each `{name}` placeholder fills a whole level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    raw: String,
+    levels: Vec<Level>,
+}
+
+impl Template {
+    /// Parse a template; it must contain `{device_id}` exactly once.
+    pub fn parse(raw: &str) -> Result<Self, LayoutError> {
+        let invalid = |reason: &str| LayoutError::InvalidTemplate {
+            template: raw.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut levels = Vec::new();
+        for level in raw.split('/') {
+            match level.strip_prefix('{').and_then(|l| l.strip_suffix('}')) {
+                Some(name) if !name.is_empty() && !name.contains(['{', '}']) => {
+                    levels.push(Level::Var(name.to_string()));
+                }
+                Some(_) => return Err(invalid("empty placeholder name")),
+                None if level.contains(['{', '}']) => {
+                    return Err(invalid("a placeholder must fill a whole level"));
+                }
+                None if level.contains(['+', '#']) => {
+                    return Err(invalid("wildcards are not allowed"));
+                }
+                None => levels.push(Level::Literal(level.to_string())),
+            }
+        }
+        let device_ids = levels
+            .iter()
+            .filter(|l| matches!(l, Level::Var(name) if name == DEVICE_ID))
+            .count();
+        if device_ids != 1 {
+            return Err(invalid("must contain {device_id} exactly once"));
+        }
+        Ok(Self {
+            raw: raw.to_string(),
+            levels,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// Placeholder names, in order.
+    pub fn vars(&self) -> impl Iterator<Item = &str> {
+        self.levels.iter().filter_map(|l| match l {
+            Level::Var(name) => Some(name.as_str()),
+            Level::Literal(_) => None,
+        })
+    }
+
+    /// Fill every placeholder from `lookup`.
+    pub fn render(&self, lookup: impl Fn(&str) -> Option<String>) -> Result<String, LayoutError> {
+        let mut levels = Vec::with_capacity(self.levels.len());
+        for level in &self.levels {
+            match level {
+                Level::Literal(text) => levels.push(text.clone()),
+                Level::Var(name) => {
+                    let value =
+                        lookup(name).ok_or_else(|| LayoutError::MissingVar(name.clone()))?;
+                    if value.is_empty() || value.contains(['/', '+', '#']) {
+                        return Err(LayoutError::InvalidValue {
+                            var: name.clone(),
+                            value,
+                        });
+                    }
+                    levels.push(value);
+                }
+            }
+        }
+        Ok(levels.join("/"))
+    }
+
+    /// Placeholder values of a matching topic.
+    pub fn capture<'t, 'a>(&'t self, topic: &'a str) -> Option<BTreeMap<&'t str, &'a str>> {
+        let mut values = BTreeMap::new();
+        let mut topic_levels = topic.split('/');
+        for level in &self.levels {
+            let value = topic_levels.next()?;
+            match level {
+                Level::Literal(text) if text == value => {}
+                Level::Var(name) if !value.is_empty() => {
+                    values.insert(name.as_str(), value);
+                }
+                _ => return None,
+            }
+        }
+        topic_levels.next().is_none().then_some(values)
+    }
+
+    /// `{device_id}` of a matching topic.
+    pub fn device_id<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        self.capture(topic)?.get(DEVICE_ID).copied()
+    }
+
+    /// This template with literal `suffix` levels appended.
+    fn join(&self, suffix: &str) -> Self {
+        let mut levels = self.levels.clone();
+        levels.extend(suffix.split('/').map(|l| Level::Literal(l.to_string())));
+        Self {
+            raw: format!("{}/{suffix}", self.raw),
+            levels,
+        }
+    }
+
+    /// Subscription filter matching every topic of this template.
+    pub fn filter(&self) -> String {
+        self.levels
+            .iter()
+            .map(|l| match l {
+                Level::Literal(text) => text.as_str(),
+                Level::Var(_) => "+",
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Template overrides for [`TopicLayout::new`]; `None` keeps the
+/// prefix-based default.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    pub telemetry: Option<String>,
+    pub ota_command: Option<String>,
+    pub ota_status: Option<String>,
+    pub status: Option<String>,
+    /// Commands go here; device replies on `.../response`.
+    pub commands: Option<String>,
+    /// Device shadow root; `.../reported` from the device, `.../delta` to it.
+    pub shadow: Option<String>,
+    /// Alert transitions published by mock-sink.
+    pub alerts: Option<String>,
+    /// Presence changes published by mock-sink.
+    pub presence: Option<String>,
+    /// Values for placeholders other than `{device_id}`, as
+    /// `tenant=acme,device_type=sensor`.
+    pub vars: Option<String>,
+}
+
+impl Templates {
+    /// Read the `MQTT_TOPIC_*_TEMPLATE` and `MQTT_TOPIC_VARS` variables;
+    /// unset or blank ones keep the default.
+    pub fn from_env() -> Self {
+        Self {
+            telemetry: env_var("MQTT_TOPIC_TELEMETRY_TEMPLATE"),
+            ota_command: env_var("MQTT_TOPIC_OTA_COMMAND_TEMPLATE"),
+            ota_status: env_var("MQTT_TOPIC_OTA_STATUS_TEMPLATE"),
+            status: env_var("MQTT_TOPIC_STATUS_TEMPLATE"),
+            commands: env_var("MQTT_TOPIC_COMMANDS_TEMPLATE"),
+            shadow: env_var("MQTT_TOPIC_SHADOW_TEMPLATE"),
+            alerts: env_var("MQTT_TOPIC_ALERTS_TEMPLATE"),
+            presence: env_var("MQTT_TOPIC_PRESENCE_TEMPLATE"),
+            vars: env_var("MQTT_TOPIC_VARS"),
+        }
+    }
+}
+
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Where device topics live, shared by the services so they agree on one
+/// scheme. Topics without a template stay at `{prefix}{device_id}/...`.
+#[derive(Debug, Clone)]
+pub struct TopicLayout {
+    prefix: String,
+    telemetry: Template,
+    ota_command: Template,
+    ota_status: Template,
+    status: Template,
+    commands: Template,
+    command_responses: Template,
+    shadow_reported: Template,
+    shadow_delta: Template,
+    alerts: Template,
+    presence: Template,
+    vars: BTreeMap<String, String>,
+}
+
+impl TopicLayout {
+    /// Defaults: telemetry on `{prefix}{device_id}`, OTA commands on
+    /// `{prefix}{device_id}/ota` and status on `{prefix}{device_id}/ota/status`;
+    /// the others on `{prefix}{device_id}/status`, `/commands`, `/shadow`,
+    /// `/alerts` and `/presence`.
+    pub fn new(prefix: &str, templates: Templates) -> Result<Self, LayoutError> {
+        let template = |custom: Option<String>, suffix: &str| {
+            Template::parse(&custom.unwrap_or_else(|| format!("{prefix}{{{DEVICE_ID}}}{suffix}")))
+        };
+        let mut vars = BTreeMap::new();
+        for entry in templates
+            .vars
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (name, value) = entry
+                .split_once('=')
+                .map(|(n, v)| (n.trim(), v.trim()))
+                .filter(|(n, v)| !n.is_empty() && !v.is_empty())
+                .ok_or_else(|| LayoutError::InvalidVars(entry.to_string()))?;
+            vars.insert(name.to_string(), value.to_string());
+        }
+        let commands = template(templates.commands, "/commands")?;
+        let shadow = template(templates.shadow, "/shadow")?;
+        let layout = Self {
+            prefix: prefix.to_string(),
+            telemetry: template(templates.telemetry, "")?,
+            ota_command: template(templates.ota_command, "/ota")?,
+            ota_status: template(templates.ota_status, "/ota/status")?,
+            status: template(templates.status, "/status")?,
+            command_responses: commands.join("response"),
+            commands,
+            shadow_reported: shadow.join("reported"),
+            shadow_delta: shadow.join("delta"),
+            alerts: template(templates.alerts, "/alerts")?,
+            presence: template(templates.presence, "/presence")?,
+            vars,
+        };
+        // Topics other than telemetry are built from the device id alone
+        for template in [
+            &layout.ota_command,
+            &layout.ota_status,
+            &layout.status,
+            &layout.commands,
+            &layout.shadow_delta,
+            &layout.alerts,
+            &layout.presence,
+        ] {
+            if let Some(var) = template
+                .vars()
+                .find(|v| *v != DEVICE_ID && !layout.vars.contains_key(*v))
+            {
+                return Err(LayoutError::MissingVar(var.to_string()));
+            }
+        }
+        Ok(layout)
+    }
+
+    /// The layout configured by `MQTT_TOPIC_PREFIX` (default
+    /// `argus/devices/`) and the variables read by [`Templates::from_env`].
+    pub fn from_env() -> Result<Self, LayoutError> {
+        let mut prefix = env_var("MQTT_TOPIC_PREFIX").unwrap_or_else(|| "argus/devices/".into());
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Self::new(&prefix, Templates::from_env())
+    }
+
+    /// The default layout under `prefix`.
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self::new(prefix, Templates::default()).expect("prefix-based layout is valid")
+    }
+
+    /// Root of the device topics that have no template.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn telemetry(&self) -> &Template {
+        &self.telemetry
+    }
+
+    pub fn ota_status(&self) -> &Template {
+        &self.ota_status
+    }
+
+    pub fn status(&self) -> &Template {
+        &self.status
+    }
+
+    pub fn command_responses(&self) -> &Template {
+        &self.command_responses
+    }
+
+    pub fn shadow_reported(&self) -> &Template {
+        &self.shadow_reported
+    }
+
+    fn static_var(&self, name: &str) -> Option<String> {
+        self.vars.get(name).cloned()
+    }
+
+    /// Telemetry topic for `device_id`; other placeholders come from
+    /// `lookup`, then from the configured variables.
+    pub fn telemetry_topic(
+        &self,
+        device_id: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<String, LayoutError> {
+        self.telemetry.render(|name| match name {
+            DEVICE_ID => Some(device_id.to_string()),
+            _ => lookup(name).or_else(|| self.static_var(name)),
+        })
+    }
+
+    /// Render a template whose only per-device value is the device id.
+    pub fn device_topic(
+        &self,
+        template: &Template,
+        device_id: &str,
+    ) -> Result<String, LayoutError> {
+        template.render(|name| match name {
+            DEVICE_ID => Some(device_id.to_string()),
+            _ => self.static_var(name),
+        })
+    }
+
+    pub fn ota_command_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.ota_command, device_id)
+    }
+
+    pub fn ota_status_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.ota_status, device_id)
+    }
+
+    pub fn command_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.commands, device_id)
+    }
+
+    pub fn command_response_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.command_responses, device_id)
+    }
+
+    pub fn shadow_delta_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.shadow_delta, device_id)
+    }
+
+    pub fn alert_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.alerts, device_id)
+    }
+
+    pub fn presence_topic(&self, device_id: &str) -> Result<String, LayoutError> {
+        self.device_topic(&self.presence, device_id)
+    }
+
+    /// Device id of a status, command, shadow, alert, presence or OTA topic.
+    pub fn device_id<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        [
+            &self.status,
+            &self.commands,
+            &self.command_responses,
+            &self.shadow_reported,
+            &self.shadow_delta,
+            &self.alerts,
+            &self.presence,
+            &self.ota_command,
+            &self.ota_status,
+        ]
+        .into_iter()
+        .find_map(|template| template.device_id(topic))
+    }
+
+    /// Device id of an OTA status topic.
+    pub fn parse_ota_status<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        self.ota_status.device_id(topic)
+    }
+}
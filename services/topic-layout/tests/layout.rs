@@ -0,0 +1,203 @@
+use topic_layout::{LayoutError, Template, Templates, TopicLayout};
+
+#[test]
+fn templates_render_capture_and_filter() {
+    let template =
+        Template::parse("{tenant}/{device_type}/{device_id}/telemetry/{channel}").unwrap();
+    let topic = template.render(|name| Some(format!("{name}-x"))).unwrap();
+    assert_eq!(
+        topic,
+        "tenant-x/device_type-x/device_id-x/telemetry/channel-x"
+    );
+
+    let values = template.capture("acme/sensor/dev-1/telemetry/env").unwrap();
+    assert_eq!(values["tenant"], "acme");
+    assert_eq!(values["channel"], "env");
+    assert_eq!(
+        template.device_id("acme/sensor/dev-1/telemetry/env"),
+        Some("dev-1")
+    );
+    assert_eq!(template.device_id("acme/sensor/dev-1/status/env"), None);
+    assert_eq!(template.device_id("acme/sensor/dev-1/telemetry"), None);
+    assert_eq!(template.filter(), "+/+/+/telemetry/+");
+
+    let err = template.render(|name| (name != "channel").then(|| "x".into()));
+    assert_eq!(err, Err(LayoutError::MissingVar("channel".into())));
+    assert!(matches!(
+        template.render(|_| Some("a/b".into())),
+        Err(LayoutError::InvalidValue { .. })
+    ));
+}
+
+#[test]
+fn templates_must_be_whole_levels_with_one_device_id() {
+    for raw in [
+        "argus/devices",
+        "argus/dev-{device_id}",
+        "argus/{}/{device_id}",
+        "argus/+/{device_id}",
+        "{device_id}/{device_id}",
+    ] {
+        assert!(
+            matches!(
+                Template::parse(raw),
+                Err(LayoutError::InvalidTemplate { .. })
+            ),
+            "{raw}"
+        );
+    }
+}
+
+#[test]
+fn default_layout_keeps_prefix_topics() {
+    let layout = TopicLayout::with_prefix("argus/devices/");
+    assert_eq!(
+        layout.telemetry_topic("dev-1", |_| None).unwrap(),
+        "argus/devices/dev-1"
+    );
+    assert_eq!(layout.telemetry().filter(), "argus/devices/+");
+    assert_eq!(
+        layout.ota_command_topic("dev-1").unwrap(),
+        "argus/devices/dev-1/ota"
+    );
+    assert_eq!(
+        layout.parse_ota_status("argus/devices/dev-1/ota/status"),
+        Some("dev-1")
+    );
+    assert_eq!(layout.parse_ota_status("argus/devices/dev-1/ota"), None);
+    assert_eq!(layout.parse_ota_status("argus/devices//ota/status"), None);
+    assert_eq!(
+        layout.command_response_topic("dev-1").unwrap(),
+        "argus/devices/dev-1/commands/response"
+    );
+    assert_eq!(
+        layout.shadow_reported().filter(),
+        "argus/devices/+/shadow/reported"
+    );
+    assert_eq!(
+        layout.device_id("argus/devices/dev-1/status"),
+        Some("dev-1")
+    );
+    assert_eq!(
+        layout.alert_topic("dev-1").unwrap(),
+        "argus/devices/dev-1/alerts"
+    );
+    assert_eq!(
+        layout.presence_topic("dev-1").unwrap(),
+        "argus/devices/dev-1/presence"
+    );
+}
+
+#[test]
+fn status_command_and_shadow_templates_follow_the_device() {
+    let layout = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            telemetry: Some("{tenant}/{device_id}/telemetry".into()),
+            status: Some("{tenant}/{device_id}/status".into()),
+            commands: Some("{tenant}/{device_id}/cmd".into()),
+            shadow: Some("{tenant}/{device_id}/shadow".into()),
+            vars: Some("tenant=acme".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(layout.status().filter(), "+/+/status");
+    assert_eq!(layout.command_topic("dev-1").unwrap(), "acme/dev-1/cmd");
+    assert_eq!(
+        layout
+            .command_responses()
+            .device_id("acme/dev-1/cmd/response"),
+        Some("dev-1")
+    );
+    assert_eq!(
+        layout.shadow_delta_topic("dev-1").unwrap(),
+        "acme/dev-1/shadow/delta"
+    );
+    assert_eq!(
+        layout.device_id("acme/dev-1/shadow/reported"),
+        Some("dev-1")
+    );
+    assert_eq!(layout.device_id("argus/devices/dev-1/status"), None);
+}
+
+#[test]
+fn alert_and_presence_templates_follow_the_device() {
+    let layout = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            alerts: Some("{tenant}/alerts/{device_id}".into()),
+            presence: Some("{tenant}/{device_id}/online".into()),
+            vars: Some("tenant=acme".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(layout.alert_topic("dev-1").unwrap(), "acme/alerts/dev-1");
+    assert_eq!(layout.presence_topic("dev-1").unwrap(), "acme/dev-1/online");
+    assert_eq!(layout.device_id("acme/alerts/dev-1"), Some("dev-1"));
+
+    // Only configured variables can fill them
+    let err = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            alerts: Some("{site}/{device_id}/alerts".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, LayoutError::MissingVar("site".into()));
+}
+
+#[test]
+fn custom_layout_uses_configured_variables() {
+    let layout = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            telemetry: Some("{tenant}/{device_type}/{device_id}/telemetry/{channel}".into()),
+            ota_command: Some("{tenant}/ota/{device_id}/cmd".into()),
+            ota_status: Some("{tenant}/ota/{device_id}/status".into()),
+            vars: Some("tenant=acme, device_type=sensor".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap();
+    let topic = layout
+        .telemetry_topic("dev-1", |name| (name == "channel").then(|| "env".into()))
+        .unwrap();
+    assert_eq!(topic, "acme/sensor/dev-1/telemetry/env");
+    assert_eq!(
+        layout.ota_command_topic("dev-1").unwrap(),
+        "acme/ota/dev-1/cmd"
+    );
+    assert_eq!(
+        layout.parse_ota_status("acme/ota/dev-1/status"),
+        Some("dev-1")
+    );
+    assert_eq!(
+        layout.ota_status_topic("dev-1").unwrap(),
+        "acme/ota/dev-1/status"
+    );
+    assert_eq!(layout.prefix(), "argus/devices/");
+
+    // OTA topics cannot depend on per-reading values
+    let err = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            ota_command: Some("{tenant}/{device_id}/ota".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, LayoutError::MissingVar("tenant".into()));
+
+    let err = TopicLayout::new(
+        "argus/devices/",
+        Templates {
+            vars: Some("tenant".into()),
+            ..Templates::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err, LayoutError::InvalidVars("tenant".into()));
+}